@@ -9,7 +9,11 @@ use std::{
 };
 
 use clap::Parser;
-use hardware::instructions::INSTRUCTIONS;
+use hardware::{
+    dat::{Dat, RomHashes},
+    header::CartridgeHeader,
+    instructions::INSTRUCTIONS,
+};
 use tracing::{error, info, warn};
 
 fn main() {
@@ -29,8 +33,18 @@ fn main() {
         save_bytes(&bytes).expect("not sure how we got here");
     }
 
-    let header = parse_header(&bytes);
-    info!("{:#?}", header);
+    match CartridgeHeader::parse(&bytes) {
+        Ok(header) => info!("{:#?}", header),
+        Err(e) => warn!("No cartridge header: {e}"),
+    }
+    info!("{}", RomHashes::compute(&bytes));
+    if let Some(dat) = args.dat {
+        match Dat::load(dat).map(|dat| dat.identify(&bytes)) {
+            Ok(Some(identity)) => info!("{identity}"),
+            Ok(None) => warn!("ROM is not in the DAT"),
+            Err(e) => error!("{e:#}"),
+        }
+    }
     disassemble(&bytes);
 }
 
@@ -41,58 +55,10 @@ struct Args {
 
     #[clap(short, long, default_value = "false")]
     save: bool,
-}
 
-#[derive(Debug)]
-struct DMGHeader {
-    entry_point: Vec<u8>,
-    logo: Vec<u8>,
-    title: Vec<u8>,
-    licensee: Vec<u8>,
-    sgb_flag: u8,
-    cartridge_type: u8,
-    rom_size: u8,
-    ram_size: u8,
-    destination_code: u8,
-    old_licensee_code: u8,
-    mask_rom_version_number: u8,
-    header_checksum: u8,
-    global_checksum: u16,
-}
-
-fn parse_header(bytes: &[u8]) -> DMGHeader {
-    let entry_point = bytes[0x0100..0x0104].to_vec();
-    let logo = bytes[0x0104..0x0134].to_vec();
-    let title = bytes[0x0134..0x0143].to_vec();
-    let licensee = bytes[0x0144..0x0146].to_vec();
-    let sgb_flag = bytes[0x0146];
-    let cartridge_type = bytes[0x0147];
-    let rom_size = bytes[0x0148];
-    let ram_size = bytes[0x0149];
-    let destination_code = bytes[0x014A];
-    let old_licensee_code = bytes[0x014B];
-    let mask_rom_version_number = bytes[0x014C];
-    let header_checksum = bytes[0x014D];
-
-    let global_checksum = bytes[0x014E..0x0150]
-        .iter()
-        .fold(0, |acc, n| acc + u16::from(*n));
-
-    DMGHeader {
-        entry_point,
-        logo,
-        title,
-        licensee,
-        sgb_flag,
-        cartridge_type,
-        rom_size,
-        ram_size,
-        destination_code,
-        old_licensee_code,
-        mask_rom_version_number,
-        header_checksum,
-        global_checksum,
-    }
+    /// Logiqx XML DAT to identify the ROM with
+    #[clap(long)]
+    dat: Option<String>,
 }
 
 fn setup_logs() {
@@ -2,23 +2,87 @@
 #![feature(lazy_cell)]
 #![warn(clippy::nursery, clippy::pedantic)]
 
-use std::sync::LazyLock;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
 
 use clap::Parser;
 use tracing::{error, trace, warn};
 
-use hardware::emu::run_emulation;
+use hardware::cart::{LoadOptions, MapperKind};
+use hardware::emu::{run_emulation, EmuOptions, ScreenshotOptions};
 use hardware::instructions::INSTRUCTIONS;
+use hardware::palette::PaletteChoice;
+use hardware::ppu::PpuBackend;
 
 static DEFAULT_ROM: &str = "./gbem/roms/Tetris.gb";
 
 #[derive(Debug, Parser)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
+    /// ROM image, optionally inside a .zip archive or gzip compressed
     #[clap(short, long)]
     rom: Option<String>,
 
+    /// File to load from a .zip archive, instead of the first .gb/.gbc in it
+    #[clap(long)]
+    entry: Option<String>,
+
     #[clap(short)]
     spam: bool,
+
+    /// Mapper to use instead of the one in the header, e.g. mbc5 or wisdom-tree
+    #[clap(long)]
+    mapper: Option<MapperKind>,
+
+    /// Refuse ROMs with a bad logo, checksum, size or cartridge type
+    #[clap(long)]
+    strict: bool,
+
+    /// IPS, BPS or UPS patch to apply to the ROM
+    #[clap(long)]
+    patch: Option<PathBuf>,
+
+    /// Apply game.ips, game.bps or game.ups if it's next to game.gb
+    #[clap(long)]
+    auto_patch: bool,
+
+    /// No-Intro style DAT file to identify the ROM and name its save with
+    #[clap(long)]
+    dat: Option<PathBuf>,
+
+    /// PPU to draw with: scanline, or fifo for hardware accurate mode 3 timing
+    #[clap(long, default_value_t)]
+    ppu: PpuBackend,
+
+    /// Log each VRAM or OAM access the PPU blocks, with the PC it came from
+    #[clap(long)]
+    warn_blocked_access: bool,
+
+    /// Colors: grayscale, green, pocket, light, auto to pick by title like a CGB,
+    /// or 4 (or 12, for BG, OBP0 and OBP1) comma separated #rrggbb colors
+    #[clap(long, default_value_t)]
+    palette: PaletteChoice,
+
+    /// Stop after this many frames, running as fast as possible instead of in real time
+    #[clap(long)]
+    frames: Option<u64>,
+
+    /// Save the screen to this PNG when the run ends
+    #[clap(long)]
+    screenshot: Option<PathBuf>,
+
+    /// Also save the screen once this frame is drawn, numbered next to --screenshot
+    #[clap(long, requires = "screenshot")]
+    screenshot_frame: Option<u64>,
+
+    /// Also save the screen at each `ld b, b` breakpoint, numbered next to --screenshot
+    #[clap(long, requires = "screenshot")]
+    screenshot_on_breakpoint: bool,
+
+    /// Scale screenshots up by this whole number
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    screenshot_scale: u32,
 }
 
 static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
@@ -50,7 +114,37 @@ fn main() -> Result<(), &'static str> {
         &default
     });
 
-    match run_emulation(rom) {
+    let options = LoadOptions {
+        mapper: ARGS.mapper,
+        strict: ARGS.strict,
+        archive_entry: ARGS.entry.clone(),
+        patch: ARGS.patch.clone(),
+        auto_patch: ARGS.auto_patch,
+        dat: ARGS.dat.clone(),
+    };
+
+    // Stop the loop instead of exiting, so the save is flushed on the way out
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        warn!("Failed to install the Ctrl-C handler: {e}");
+    }
+
+    let emu = EmuOptions {
+        ppu: ARGS.ppu,
+        warn_blocked_access: ARGS.warn_blocked_access,
+        palette: ARGS.palette,
+        frames: ARGS.frames,
+        screenshot: ScreenshotOptions {
+            path: ARGS.screenshot.clone(),
+            frame: ARGS.screenshot_frame,
+            on_breakpoint: ARGS.screenshot_on_breakpoint,
+            scale: ARGS.screenshot_scale,
+        },
+        stop,
+    };
+
+    match run_emulation(rom, &options, &emu) {
         Ok(()) => Ok(()),
         Err(e) => {
             eprintln!("{e}");
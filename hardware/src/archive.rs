@@ -0,0 +1,153 @@
+//! Reading ROM images, which may be compressed into a zip archive or gzip file
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Context};
+use flate2::read::GzDecoder;
+use tracing::debug;
+use zip::ZipArchive;
+
+use crate::MAX_ROM_SIZE;
+
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ROM_EXTENSIONS: [&str; 2] = ["gb", "gbc"];
+
+/// Reads the ROM at `path`, decompressing it in memory if needed.
+///
+/// In a zip archive `entry` picks the file by name, otherwise the first
+/// `.gb` or `.gbc` file is used.
+pub fn read_rom(path: impl AsRef<Path>, entry: Option<&str>) -> crate::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    decompress(bytes, entry)
+}
+
+pub fn decompress(bytes: Vec<u8>, entry: Option<&str>) -> crate::Result<Vec<u8>> {
+    if bytes.starts_with(&ZIP_MAGIC) {
+        return read_zip(bytes, entry);
+    }
+
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let rom = read_limited(GzDecoder::new(&*bytes))?;
+        debug!(
+            "Decompressed {} bytes of gzip into {}",
+            bytes.len(),
+            rom.len()
+        );
+        return Ok(rom);
+    }
+
+    Ok(bytes)
+}
+
+fn read_zip(bytes: Vec<u8>, entry: Option<&str>) -> crate::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let names = (0..archive.len())
+        .map(|i| Ok(archive.by_index(i)?.name().to_owned()))
+        .collect::<crate::Result<Vec<_>>>()?;
+
+    let name = names.iter().find(|name| {
+        entry.map_or_else(
+            || is_rom_name(name),
+            |entry| *name == entry || file_name(name) == entry,
+        )
+    });
+    let name = name.ok_or_else(|| {
+        anyhow!(
+            "No {} in the archive, it contains: {}",
+            entry.unwrap_or("Game Boy ROM"),
+            names.join(", ")
+        )
+    })?;
+
+    let rom = read_limited(archive.by_name(name)?)?;
+    debug!("Loaded {name} from a zip archive");
+    Ok(rom)
+}
+
+/// Reads at most a byte past [`MAX_ROM_SIZE`], so a zip bomb can't exhaust memory
+fn read_limited(reader: impl Read) -> crate::Result<Vec<u8>> {
+    let mut rom = Vec::new();
+    reader.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?;
+    ensure!(
+        rom.len() <= MAX_ROM_SIZE,
+        "Decompressed ROM is larger than {MAX_ROM_SIZE} bytes"
+    );
+    Ok(rom)
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom| ext.eq_ignore_ascii_case(rom))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    fn make_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_zip() {
+        let zip = make_zip(&[
+            ("readme.txt", b"hello"),
+            ("games/first.gb", b"first"),
+            ("second.GBC", b"second"),
+        ]);
+        assert_eq!(decompress(zip.clone(), None).unwrap(), b"first");
+        assert_eq!(
+            decompress(zip.clone(), Some("second.GBC")).unwrap(),
+            b"second"
+        );
+        assert_eq!(decompress(zip.clone(), Some("first.gb")).unwrap(), b"first");
+        assert!(decompress(zip, Some("third.gb")).is_err());
+
+        let zip = make_zip(&[("readme.txt", b"hello")]);
+        assert!(decompress(zip, None).is_err());
+    }
+
+    #[test]
+    fn test_gzip() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(b"rom").unwrap();
+        assert_eq!(decompress(gz.finish().unwrap(), None).unwrap(), b"rom");
+
+        assert_eq!(decompress(b"plain".to_vec(), None).unwrap(), b"plain");
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&vec![0; MAX_ROM_SIZE + 1]).unwrap();
+        assert!(decompress(gz.finish().unwrap(), None).is_err());
+
+        let zip = make_zip(&[("big.gb", &vec![0; MAX_ROM_SIZE + 1])]);
+        assert!(decompress(zip, None).is_err());
+    }
+}
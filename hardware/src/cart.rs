@@ -0,0 +1,515 @@
+//! The cartridge slot: ROM image, external RAM and the memory bank controller
+//! that maps them into 0x0000-0x7FFF and 0xA000-0xBFFF.
+//!
+//! <https://gbdev.io/pandocs/MBCs.html>
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use tracing::{debug, warn};
+
+use crate::cart::camera::CameraInput;
+use crate::cart::ir::IrEndpoint;
+use crate::cart::rtc::Clock;
+use crate::dat::Identification;
+use crate::header::{CartridgeHeader, CartridgeType};
+use crate::MAX_ROM_SIZE;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/// A memory bank controller.
+///
+/// The cartridge owns the ROM and RAM buffers and lends them to the mapper,
+/// which only keeps its own registers.
+pub trait Mapper: fmt::Debug + Send + Sync {
+    /// Read from 0x0000-0x7FFF
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8;
+    /// Write to 0x0000-0x7FFF, which is where the bank registers live
+    fn write_rom(&mut self, address: u16, value: u8);
+    /// Read from 0xA000-0xBFFF
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8;
+    /// Write to 0xA000-0xBFFF, returning whether it changed RAM
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool;
+
+    /// Replaces the host time source of the cartridge's clock, if it has one
+    fn set_clock(&mut self, _clock: Arc<dyn Clock>) {}
+
+    /// Whether the rumble motor is currently on
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Feeds the cartridge's accelerometer, in g along each axis
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Connects the cartridge's infrared LED and receiver, if it has them
+    fn set_ir(&mut self, _endpoint: Arc<dyn IrEndpoint>) {}
+
+    /// Replaces what the cartridge's image sensor sees
+    fn set_camera_input(&mut self, _input: CameraInput) {}
+
+    /// Advances hardware on the cartridge that runs on the system clock
+    fn tick(&mut self, _ram: &mut [u8], _m_cycles: u32) {}
+
+    /// Battery backed state other than RAM, saved right after it
+    fn save_footer(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_save_footer(&mut self, _footer: &[u8]) {}
+
+    /// Whether the save footer changed since the last call
+    fn take_dirty(&mut self) -> bool {
+        false
+    }
+}
+
+/// Mapper to use instead of the one the header declares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapperKind {
+    None,
+    Mbc1,
+    /// MBC1 wired for multicarts
+    Mbc1Multicart,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    Huc1,
+    Huc3,
+    Camera,
+    Tama5,
+    WisdomTree,
+}
+
+impl MapperKind {
+    const NAMES: [(Self, &'static str); 14] = [
+        (Self::None, "none"),
+        (Self::Mbc1, "mbc1"),
+        (Self::Mbc1Multicart, "mbc1m"),
+        (Self::Mbc2, "mbc2"),
+        (Self::Mbc3, "mbc3"),
+        (Self::Mbc5, "mbc5"),
+        (Self::Mbc6, "mbc6"),
+        (Self::Mbc7, "mbc7"),
+        (Self::Mmm01, "mmm01"),
+        (Self::Huc1, "huc1"),
+        (Self::Huc3, "huc3"),
+        (Self::Camera, "camera"),
+        (Self::Tama5, "tama5"),
+        (Self::WisdomTree, "wisdom-tree"),
+    ];
+
+    /// The mapper the header declares, `None` for unknown cartridge types
+    pub const fn from_header(cartridge_type: CartridgeType) -> Option<Self> {
+        use CartridgeType::*;
+        Some(match cartridge_type {
+            RomOnly | RomRam | RomRamBattery => Self::None,
+            Mmm01 | Mmm01Ram | Mmm01RamBattery => Self::Mmm01,
+            Mbc1 | Mbc1Ram | Mbc1RamBattery => Self::Mbc1,
+            Mbc2 | Mbc2Battery => Self::Mbc2,
+            Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3 | Mbc3Ram | Mbc3RamBattery => Self::Mbc3,
+            Mbc5 | Mbc5Ram | Mbc5RamBattery | Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery => {
+                Self::Mbc5
+            }
+            Mbc6 => Self::Mbc6,
+            Mbc7SensorRumbleRamBattery => Self::Mbc7,
+            HuC1RamBattery => Self::Huc1,
+            HuC3 => Self::Huc3,
+            PocketCamera => Self::Camera,
+            BandaiTama5 => Self::Tama5,
+            Unknown(_) => return None,
+        })
+    }
+}
+
+impl FromStr for MapperKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|&(kind, _)| kind)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::NAMES.iter().map(|(_, name)| *name).collect();
+                format!("unknown mapper {s:?}, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for MapperKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (_, name) = Self::NAMES.iter().find(|(kind, _)| kind == self).unwrap();
+        f.write_str(name)
+    }
+}
+
+/// How to interpret a ROM image
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Overrides both the header and the detection heuristics
+    pub mapper: Option<MapperKind>,
+    /// Refuse ROMs with header problems instead of warning about them
+    pub strict: bool,
+    /// File to load from a zip archive, instead of the first ROM in it
+    pub archive_entry: Option<String>,
+    /// IPS, BPS or UPS patch to apply before the header is parsed
+    pub patch: Option<PathBuf>,
+    /// Apply a patch next to the ROM with the same name, unless `patch` is set
+    pub auto_patch: bool,
+    /// Logiqx XML DAT to identify the ROM with
+    pub dat: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    /// The DAT entry the ROM matched, when loaded with one
+    pub identity: Option<Identification>,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapper: Box<dyn Mapper>,
+    /// RAM was written to since the last save
+    dirty: bool,
+}
+
+impl Default for Cartridge {
+    /// An empty slot, mapped like a 32 KiB ROM without RAM
+    fn default() -> Self {
+        let rom = vec![0; 2 * ROM_BANK_SIZE];
+        Self {
+            header: CartridgeHeader::parse(&rom).unwrap(),
+            identity: None,
+            rom,
+            ram: Vec::new(),
+            mapper: Box::new(NoMbc),
+            dirty: false,
+        }
+    }
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> crate::Result<Self> {
+        Self::with_options(rom, &LoadOptions::default())
+    }
+
+    pub fn with_options(rom: Vec<u8>, options: &LoadOptions) -> crate::Result<Self> {
+        if rom.len() > MAX_ROM_SIZE {
+            bail!(
+                "ROM is {} bytes, larger than the {} bytes any mapper can address",
+                rom.len(),
+                MAX_ROM_SIZE
+            );
+        }
+
+        let header = mmm01::menu_header(&rom).map_or_else(|| CartridgeHeader::parse(&rom), Ok)?;
+
+        let kind = options
+            .mapper
+            .or_else(|| unlicensed::detect(&header, &rom))
+            .or_else(|| MapperKind::from_header(header.cartridge_type));
+        let mapper = mapper_for(kind, &header, &rom);
+        debug!("Cartridge mapper: {mapper:?}");
+
+        Ok(Self {
+            ram: vec![0; ram_size(kind, &header)],
+            header,
+            identity: None,
+            rom,
+            mapper,
+            dirty: false,
+        })
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// RAM that survives power off and should be written to a save file
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        (self.header.cartridge_type.has_battery() && !self.ram.is_empty()).then_some(&*self.ram)
+    }
+
+    /// Restores RAM from a save file, ignoring anything past the cartridge's RAM size
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.mapper.set_clock(clock);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mapper.set_tilt(x, y);
+    }
+
+    pub fn set_ir(&mut self, endpoint: Arc<dyn IrEndpoint>) {
+        self.mapper.set_ir(endpoint);
+    }
+
+    pub fn set_camera_input(&mut self, input: CameraInput) {
+        self.mapper.set_camera_input(input);
+    }
+
+    pub fn tick(&mut self, m_cycles: u32) {
+        self.mapper.tick(&mut self.ram, m_cycles);
+    }
+
+    /// Everything a save file should contain: battery backed RAM and whatever the mapper adds
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.header.cartridge_type.has_battery() {
+            return None;
+        }
+
+        let mut data = self.ram.clone();
+        data.extend(self.mapper.save_footer());
+        (!data.is_empty()).then_some(data)
+    }
+
+    /// Whether the save data changed since the last call
+    pub fn take_dirty(&mut self) -> bool {
+        let footer = self.mapper.take_dirty();
+        std::mem::take(&mut self.dirty) || footer
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let split = data.len().min(self.ram.len());
+        let (ram, footer) = data.split_at(split);
+        self.load_battery_ram(ram);
+        if !footer.is_empty() {
+            self.mapper.load_save_footer(footer);
+        }
+    }
+
+    /// Writes straight into RAM bank `bank`, whatever the mapper has selected
+    pub fn poke_ram(&mut self, bank: u8, address: u16, value: u8) {
+        if let Some(i) = banked_ram(&self.ram, usize::from(bank), address) {
+            self.ram[i] = value;
+            self.dirty = true;
+        }
+    }
+
+    /// Overwrites the start of the ROM image, used to map in the boot ROM
+    pub fn overlay_rom(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(self.rom.len());
+        self.rom[..len].copy_from_slice(&bytes[..len]);
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7FFF => self.mapper.read_rom(&self.rom, address),
+            0xA000..=0xBFFF => self.mapper.read_ram(&self.ram, address),
+            _ => unreachable!("{address:#06x} is not on the cartridge bus"),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x7FFF => self.mapper.write_rom(address, value),
+            0xA000..=0xBFFF => {
+                if self.mapper.write_ram(&mut self.ram, address, value) {
+                    self.dirty = true;
+                }
+            }
+            _ => unreachable!("{address:#06x} is not on the cartridge bus"),
+        }
+    }
+}
+
+fn mapper_for(kind: Option<MapperKind>, header: &CartridgeHeader, rom: &[u8]) -> Box<dyn Mapper> {
+    let Some(kind) = kind else {
+        warn!(
+            "Unknown cartridge type {:?}, mapping it without banking",
+            header.cartridge_type
+        );
+        return Box::new(NoMbc);
+    };
+
+    match kind {
+        MapperKind::None => Box::new(NoMbc),
+        MapperKind::Mbc1 => Box::new(mbc1::Mbc1::new(rom)),
+        MapperKind::Mbc1Multicart => Box::new(mbc1::Mbc1::multicart()),
+        MapperKind::Mbc2 => Box::new(mbc2::Mbc2::default()),
+        MapperKind::Mbc3 => Box::new(mbc3::Mbc3::new(header.cartridge_type.has_timer())),
+        MapperKind::Mbc5 => Box::new(mbc5::Mbc5::new(header.cartridge_type.has_rumble())),
+        MapperKind::Mbc6 => Box::new(mbc6::Mbc6::default()),
+        MapperKind::Mbc7 => Box::new(mbc7::Mbc7::default()),
+        MapperKind::Mmm01 => Box::new(mmm01::Mmm01::default()),
+        MapperKind::Huc1 => Box::new(huc1::Huc1::default()),
+        MapperKind::Huc3 => Box::new(huc3::Huc3::default()),
+        MapperKind::Camera => Box::new(camera::Camera::default()),
+        MapperKind::Tama5 => Box::new(tama5::Tama5::default()),
+        MapperKind::WisdomTree => Box::new(unlicensed::WisdomTree::default()),
+    }
+}
+
+/// Some controllers have RAM built in, which the header reports as 0
+const fn ram_size(kind: Option<MapperKind>, header: &CartridgeHeader) -> usize {
+    match kind {
+        Some(MapperKind::Mbc2) => mbc2::MBC2_RAM_SIZE,
+        Some(MapperKind::Mbc7) => mbc7::EEPROM_SIZE,
+        Some(MapperKind::Tama5) => tama5::TAMA5_RAM_SIZE,
+        _ => header.ram_size,
+    }
+}
+
+/// Reads `address` from the 16 KiB ROM bank `bank`, wrapping around
+/// the image the same way the unconnected address lines would
+pub fn banked_rom(rom: &[u8], bank: usize, address: u16) -> u8 {
+    if rom.is_empty() {
+        return 0xFF;
+    }
+
+    let offset = bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+    rom[offset % rom.len()]
+}
+
+/// Index of `address` in the 8 KiB RAM bank `bank`, or `None` without any RAM
+pub const fn banked_ram(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+
+    let offset = bank * RAM_BANK_SIZE + (address as usize & (RAM_BANK_SIZE - 1));
+    Some(offset % ram.len())
+}
+
+/// Stores `value` at `i`, returning whether that changed the byte
+pub fn store(ram: &mut [u8], i: usize, value: u8) -> bool {
+    std::mem::replace(&mut ram[i], value) != value
+}
+
+/// 32 KiB of ROM mapped directly, with optional RAM
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoMbc;
+
+impl Mapper for NoMbc {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        banked_ram(ram, 0, address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        banked_ram(ram, 0, address).is_some_and(|i| store(ram, i, value))
+    }
+}
+
+pub mod camera;
+pub mod huc1;
+pub mod huc3;
+pub mod ir;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod mbc6;
+pub mod mbc7;
+pub mod mmm01;
+pub mod rtc;
+pub mod tama5;
+pub mod unlicensed;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::make_rom;
+
+    #[test]
+    fn test_large_rom() {
+        // 128 KiB used to overflow the u16 length passed to write_range
+        let mut rom = make_rom("BIG", 0x00, 2, 0);
+        rom[0x1_0000] = 0x42;
+        let cart = Cartridge::new(rom).unwrap();
+        assert_eq!(cart.rom().len(), 0x2_0000);
+        assert_eq!(banked_rom(cart.rom(), 4, 0x4000), 0x42);
+
+        let too_big = vec![0; MAX_ROM_SIZE * 2];
+        assert!(Cartridge::new(too_big).is_err());
+    }
+
+    #[test]
+    fn test_rom_ram() {
+        let mut cart = Cartridge::new(make_rom("RAM", 0x08, 0, 2)).unwrap();
+        cart.write(0x0000, 0x12);
+        assert_eq!(cart.read(0x0000), 0x00);
+
+        cart.write(0xA000, 0x34);
+        cart.write(0xBFFF, 0x56);
+        assert_eq!(cart.read(0xA000), 0x34);
+        assert_eq!(cart.read(0xBFFF), 0x56);
+
+        let mut cart = Cartridge::new(make_rom("NORAM", 0x00, 0, 0)).unwrap();
+        cart.write(0xA000, 0x34);
+        assert_eq!(cart.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_dirty() {
+        // MBC1 with 8 KiB of RAM, disabled until 0x0A is written below 0x2000
+        let mut cart = Cartridge::new(make_rom("DIRTY", 0x03, 0, 2)).unwrap();
+        cart.write(0xA000, 0x34);
+        assert!(!cart.take_dirty());
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x34);
+        assert!(cart.take_dirty());
+        assert!(!cart.take_dirty());
+
+        // Rewriting the same value changes nothing
+        cart.write(0xA000, 0x34);
+        assert!(!cart.take_dirty());
+    }
+
+    #[test]
+    fn test_forced_mapper() {
+        let mut rom = make_rom("HOMEBREW", 0x00, 1, 0);
+        rom[0x4000 * 3] = 0x42;
+        let options = LoadOptions {
+            mapper: Some("MBC5".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut cart = Cartridge::with_options(rom, &options).unwrap();
+        cart.write(0x2000, 0x03);
+        assert_eq!(cart.read(0x4000), 0x42);
+
+        assert!("mbc4".parse::<MapperKind>().is_err());
+        for (kind, name) in MapperKind::NAMES {
+            assert_eq!(kind.to_string().parse::<MapperKind>(), Ok(kind), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_mmm01_menu_header() {
+        // The first game's header says MBC1, the menu at the end says MMM01
+        let mut rom = make_rom("GAME", 0x01, 2, 0);
+        let menu = make_rom("MENU", 0x0B, 0, 0);
+        let start = rom.len() - menu.len();
+        rom[start..].copy_from_slice(&menu);
+        let cart = Cartridge::new(rom).unwrap();
+        assert_eq!(cart.header.cartridge_type, CartridgeType::Mmm01);
+        assert_eq!(cart.header.title, "MENU");
+
+        let cart = Cartridge::new(make_rom("GAME", 0x01, 2, 0)).unwrap();
+        assert_eq!(cart.header.cartridge_type, CartridgeType::Mbc1);
+    }
+}
@@ -0,0 +1,519 @@
+//! Game Boy Camera (Pocket Camera) with its M64282FP sensor
+//!
+//! <https://gbdev.io/pandocs/Gameboy_Camera.html>
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context};
+use tracing::{debug, warn};
+
+use crate::cart::{banked_ram, banked_rom, store, Mapper};
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+/// Where the captured picture is written to, in RAM bank 0
+const IMAGE_OFFSET: usize = 0x100;
+/// RAM bank value that maps the sensor registers instead of RAM
+const REGISTER_BANK: u8 = 0x10;
+const REGISTER_COUNT: usize = 0x36;
+
+/// Edge enhancement ratio selected by A004 bits 4-6
+const EDGE_RATIOS: [f32; 8] = [0.50, 0.75, 1.00, 1.25, 2.00, 3.00, 4.00, 5.00];
+
+/// Greyscale frames fed to the sensor, 0 is black and 255 is white.
+///
+/// Each capture uses the next frame, looping back to the first one.
+#[derive(Debug, Clone)]
+pub struct CameraInput {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl Default for CameraInput {
+    /// A mid grey frame, about what the lens sees with the cover on
+    fn default() -> Self {
+        Self {
+            frames: vec![vec![0x80; SENSOR_WIDTH * SENSOR_HEIGHT]],
+            next: 0,
+        }
+    }
+}
+
+impl CameraInput {
+    /// Frames must already be `SENSOR_WIDTH` x `SENSOR_HEIGHT`, and there must be at least one
+    pub fn new(frames: Vec<Vec<u8>>) -> crate::Result<Self> {
+        ensure!(!frames.is_empty(), "The camera needs at least one frame");
+        if let Some(frame) = frames
+            .iter()
+            .find(|frame| frame.len() != SENSOR_WIDTH * SENSOR_HEIGHT)
+        {
+            bail!(
+                "Camera frames must be {}x{} pixels, got {} bytes",
+                SENSOR_WIDTH,
+                SENSOR_HEIGHT,
+                frame.len()
+            );
+        }
+        Ok(Self { frames, next: 0 })
+    }
+
+    /// Loads a single image, or every image in a directory in file name order.
+    ///
+    /// PNG and binary PGM files are supported, and scaled to the sensor's size.
+    pub fn from_path(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Self::new(vec![load_image(path)?]);
+        }
+
+        let mut files = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        files.sort();
+
+        let frames = files
+            .iter()
+            .filter(|file| {
+                file.extension().is_some_and(|ext| {
+                    ext.eq_ignore_ascii_case("png") || ext.eq_ignore_ascii_case("pgm")
+                })
+            })
+            .map(|file| load_image(file))
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        if frames.is_empty() {
+            bail!("No images in {}", path.display());
+        }
+
+        Self::new(frames)
+    }
+
+    fn next_frame(&mut self) -> &[u8] {
+        let frame = self.next % self.frames.len();
+        self.next = self.next.wrapping_add(1);
+        &self.frames[frame]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    registers: [u8; REGISTER_COUNT],
+    /// M-cycles left until the capture in progress is finished
+    busy: u32,
+    /// The dithered picture, written to RAM once the capture finishes
+    pending: Option<Vec<u8>>,
+    input: CameraInput,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            busy: 0,
+            pending: None,
+            input: CameraInput::default(),
+        }
+    }
+}
+
+impl Camera {
+    const fn registers_mapped(&self) -> bool {
+        self.ram_bank & REGISTER_BANK != 0
+    }
+
+    const fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]])
+    }
+
+    /// Capture length in M-cycles, which mostly depends on the exposure time
+    fn capture_time(&self) -> u32 {
+        let n = self.registers[1] & 0x80 != 0;
+        32446 + if n { 0 } else { 512 } + 16 * u32::from(self.exposure())
+    }
+
+    fn start_capture(&mut self) {
+        let frame = self.input.next_frame().to_vec();
+        self.pending = Some(self.process(&frame));
+        self.busy = self.capture_time();
+        debug!(
+            "Camera capture started, exposure {:#06x}, {} cycles",
+            self.exposure(),
+            self.busy
+        );
+    }
+
+    /// Runs a frame through the sensor and the cartridge's dithering,
+    /// returning 2bpp tile data for a 16x14 tile picture
+    fn process(&self, frame: &[u8]) -> Vec<u8> {
+        let sensed = self.sense(frame);
+        let enhanced = self.edge_enhance(&sensed);
+        self.dither(&enhanced)
+    }
+
+    /// Light accumulated over the exposure time, amplified by the gain
+    fn sense(&self, frame: &[u8]) -> Vec<f32> {
+        let gain = self.registers[1] & 0x1F;
+        let exposure = f32::from(self.exposure());
+        // A gain of 0 is 14 dB, each step adds about 1.5 dB
+        let gain = 10f32.powf(f32::from(gain).mul_add(1.5, 14.0) / 20.0) / 5.0;
+        // The camera ROM settles around 0x0300 in a well lit room
+        let exposure = exposure / f32::from(0x0300u16);
+
+        frame
+            .iter()
+            .map(|pixel| (f32::from(*pixel) * exposure * gain).min(255.0))
+            .collect()
+    }
+
+    /// The sensor's edge enhancement, selected by N and VH in A001
+    fn edge_enhance(&self, image: &[f32]) -> Vec<f32> {
+        let n = self.registers[1] & 0x80 != 0;
+        let vh = (self.registers[1] >> 5) & 0x03;
+        let alpha = EDGE_RATIOS[usize::from((self.registers[4] >> 4) & 0x07)];
+        let invert = self.registers[4] & 0x08 != 0;
+
+        let at = |x: usize, y: usize| image[y * SENSOR_WIDTH + x];
+        let mut out = Vec::with_capacity(image.len());
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let p = at(x, y);
+                let left = at(x.saturating_sub(1), y);
+                let right = at((x + 1).min(SENSOR_WIDTH - 1), y);
+                let up = at(x, y.saturating_sub(1));
+                let down = at(x, (y + 1).min(SENSOR_HEIGHT - 1));
+
+                let value = match (n, vh) {
+                    // Horizontal edges only
+                    (false, 1) => (alpha * 2.0).mul_add(p, -alpha * (left + right)) + p,
+                    // Vertical edges, which need the N bit to be set on hardware
+                    (true, 2) => (alpha * 2.0).mul_add(p, -alpha * (up + down)) + p,
+                    // 2D enhancement
+                    (true, 3) => (alpha * 4.0).mul_add(p, -alpha * (left + right + up + down)) + p,
+                    _ => p,
+                };
+
+                out.push(if invert { 255.0 - value } else { value });
+            }
+        }
+
+        out
+    }
+
+    /// Turns the analog output into 4 shades using the 4x4 threshold matrix
+    fn dither(&self, image: &[f32]) -> Vec<u8> {
+        let offset = self.output_offset();
+        let matrix = &self.registers[6..REGISTER_COUNT];
+        let mut tiles = vec![0; SENSOR_WIDTH * SENSOR_HEIGHT / 4];
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let value = (image[y * SENSOR_WIDTH + x] + offset).clamp(0.0, 255.0);
+                let cell = ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &matrix[cell..cell + 3];
+
+                // 3 is black, the lightest pixels stay 0
+                let shade = match thresholds.iter().position(|t| value < f32::from(*t)) {
+                    Some(0) => 3,
+                    Some(1) => 2,
+                    Some(_) => 1,
+                    None => 0,
+                };
+
+                let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+                let row = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                tiles[row] |= (shade & 1) << bit;
+                tiles[row + 1] |= (shade >> 1) << bit;
+            }
+        }
+
+        tiles
+    }
+
+    /// A005 moves the output up or down, bit 5 is the sign
+    fn output_offset(&self) -> f32 {
+        let register = self.registers[5];
+        let magnitude = f32::from(register & 0x1F) * 4.0;
+        if register & 0x20 != 0 {
+            magnitude
+        } else {
+            -magnitude
+        }
+    }
+}
+
+impl Mapper for Camera {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x1F,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.registers_mapped() {
+            // Only the capture flag can be read back
+            return match address & 0x7F {
+                0x00 => self.registers[0] & 0x06 | u8::from(self.busy > 0),
+                _ => 0x00,
+            };
+        }
+
+        // RAM can't be read while the sensor is writing to it
+        if self.busy > 0 {
+            return 0x00;
+        }
+
+        banked_ram(ram, usize::from(self.ram_bank & 0x0F), address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.registers_mapped() {
+            let register = usize::from(address & 0x7F);
+            if register >= REGISTER_COUNT {
+                return false;
+            }
+
+            self.registers[register] = value;
+            if register == 0 && value & 0x01 == 1 && self.busy == 0 {
+                self.start_capture();
+            }
+            return false;
+        }
+
+        if !self.ram_enabled || self.busy > 0 {
+            return false;
+        }
+
+        banked_ram(ram, usize::from(self.ram_bank & 0x0F), address)
+            .is_some_and(|i| store(ram, i, value))
+    }
+
+    fn tick(&mut self, ram: &mut [u8], m_cycles: u32) {
+        if self.busy == 0 {
+            return;
+        }
+
+        self.busy = self.busy.saturating_sub(m_cycles);
+        if self.busy == 0 {
+            self.registers[0] &= !0x01;
+            // Carts forced onto this mapper may not have the RAM to hold the picture
+            if let Some(image) = self.pending.take().filter(|_| ram.len() > IMAGE_OFFSET) {
+                let end = (IMAGE_OFFSET + image.len()).min(ram.len());
+                ram[IMAGE_OFFSET..end].copy_from_slice(&image[..end - IMAGE_OFFSET]);
+            }
+        }
+    }
+
+    fn set_camera_input(&mut self, input: CameraInput) {
+        self.input = input;
+    }
+}
+
+/// Loads an image as greyscale, scaled to fill the sensor
+fn load_image(path: &Path) -> crate::Result<Vec<u8>> {
+    let is_pgm = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pgm"));
+    let (width, height, pixels) = if is_pgm {
+        load_pgm(path)
+    } else {
+        load_png(path)
+    }
+    .with_context(|| format!("Failed to load camera image {}", path.display()))?;
+
+    ensure!(
+        width > 0 && height > 0,
+        "Camera image {} is empty",
+        path.display()
+    );
+
+    if width != SENSOR_WIDTH || height != SENSOR_HEIGHT {
+        warn!(
+            "Scaling {}x{} camera image to {}x{}",
+            width, height, SENSOR_WIDTH, SENSOR_HEIGHT
+        );
+    }
+
+    Ok((0..SENSOR_WIDTH * SENSOR_HEIGHT)
+        .map(|i| {
+            let x = (i % SENSOR_WIDTH) * width / SENSOR_WIDTH;
+            let y = (i / SENSOR_WIDTH) * height / SENSOR_HEIGHT;
+            pixels[y * width + x]
+        })
+        .collect())
+}
+
+fn load_png(path: &Path) -> crate::Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+
+    let channels = info.color_type.samples();
+    let pixels = buffer[..info.buffer_size()]
+        .chunks(channels)
+        .map(|pixel| match pixel {
+            // Rec. 601 luma, ignoring alpha
+            [r, g, b, ..] => {
+                ((u32::from(*r) * 299 + u32::from(*g) * 587 + u32::from(*b) * 114) / 1000) as u8
+            }
+            [luma, ..] => *luma,
+            [] => 0,
+        })
+        .collect();
+
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+/// Binary greyscale netpbm, with an 8 bit maximum value
+fn load_pgm(path: &Path) -> crate::Result<(usize, usize, Vec<u8>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut fields = Vec::new();
+    let mut line = String::new();
+
+    while fields.len() < 4 {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("Truncated PGM header");
+        }
+        let content = line.split('#').next().unwrap_or_default();
+        fields.extend(content.split_whitespace().map(str::to_owned));
+    }
+
+    if fields[0] != "P5" || fields[3] != "255" {
+        bail!("Only binary 8 bit PGM files are supported");
+    }
+
+    let width: usize = fields[1].parse()?;
+    let height: usize = fields[2].parse()?;
+    let mut pixels = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut pixels)?;
+    if pixels.len() < width * height {
+        bail!("Truncated PGM image data");
+    }
+
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::{Cartridge, LoadOptions, MapperKind};
+    use crate::testing::{make_rom, temp_path};
+
+    fn camera_cart(input: CameraInput) -> Cartridge {
+        let mut cart = Cartridge::new(make_rom("GAMEBOYCAMERA", 0xFC, 5, 4)).unwrap();
+        cart.set_camera_input(input);
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x10);
+        // Unity-ish gain and exposure, with an even threshold matrix
+        cart.write(0xA001, 0x00);
+        cart.write(0xA002, 0x03);
+        cart.write(0xA003, 0x00);
+        for cell in 0..16 {
+            cart.write(0xA006 + cell * 3, 0x40);
+            cart.write(0xA007 + cell * 3, 0x80);
+            cart.write(0xA008 + cell * 3, 0xC0);
+        }
+        cart
+    }
+
+    fn capture(cart: &mut Cartridge) {
+        cart.write(0x4000, 0x10);
+        cart.write(0xA000, 0x01);
+        assert_eq!(cart.read(0xA000) & 1, 1);
+        cart.tick(0x0010_0000);
+        assert_eq!(cart.read(0xA000) & 1, 0);
+        cart.write(0x4000, 0x00);
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut frame = vec![0xFF; SENSOR_WIDTH * SENSOR_HEIGHT];
+        frame[..SENSOR_WIDTH * 8].fill(0x00);
+        let mut cart = camera_cart(CameraInput::new(vec![frame]).unwrap());
+
+        capture(&mut cart);
+        // First tile row is black, the rest is white
+        assert_eq!(cart.ram()[IMAGE_OFFSET..IMAGE_OFFSET + 2], [0xFF, 0xFF]);
+        let second_row = IMAGE_OFFSET + 16 * 16;
+        assert_eq!(cart.ram()[second_row..second_row + 2], [0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_exposure_and_frames() {
+        let dark = vec![0x20; SENSOR_WIDTH * SENSOR_HEIGHT];
+        let light = vec![0xFF; SENSOR_WIDTH * SENSOR_HEIGHT];
+        let mut cart = camera_cart(CameraInput::new(vec![dark, light]).unwrap());
+
+        capture(&mut cart);
+        assert_eq!(cart.ram()[IMAGE_OFFSET..IMAGE_OFFSET + 2], [0xFF, 0xFF]);
+        capture(&mut cart);
+        assert_eq!(cart.ram()[IMAGE_OFFSET..IMAGE_OFFSET + 2], [0x00, 0x00]);
+
+        // Loops back to the dark frame, but a long exposure brightens it
+        cart.write(0x4000, 0x10);
+        cart.write(0xA002, 0x30);
+        capture(&mut cart);
+        assert_eq!(cart.ram()[IMAGE_OFFSET..IMAGE_OFFSET + 2], [0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_capture_without_ram() {
+        let options = LoadOptions {
+            mapper: Some(MapperKind::Camera),
+            ..LoadOptions::default()
+        };
+        let mut cart = Cartridge::with_options(make_rom("NORAM", 0x00, 0, 0), &options).unwrap();
+        capture(&mut cart);
+        assert!(cart.ram().is_empty());
+    }
+
+    #[test]
+    fn test_load_pgm() {
+        let path = temp_path("camera.pgm");
+        let mut data = b"P5\n# test\n2 2\n255\n".to_vec();
+        data.extend([0x00, 0x40, 0x80, 0xFF]);
+        fs::write(&path, data).unwrap();
+
+        let input = CameraInput::from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let frame = &input.frames[0];
+        assert_eq!(frame.len(), SENSOR_WIDTH * SENSOR_HEIGHT);
+        assert_eq!(frame[0], 0x00);
+        assert_eq!(frame[SENSOR_WIDTH - 1], 0x40);
+        assert_eq!(frame[SENSOR_WIDTH * (SENSOR_HEIGHT - 1)], 0x80);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(CameraInput::new(vec![]).is_err());
+        assert!(CameraInput::new(vec![vec![0x80; 16]]).is_err());
+
+        let path = temp_path("camera-empty.pgm");
+        fs::write(&path, b"P5\n0 0\n255\n").unwrap();
+        let result = CameraInput::from_path(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! <https://gbdev.io/pandocs/HuC1.html>
+use std::sync::Arc;
+
+use crate::cart::ir::{ir_read, IrEndpoint, NoIr};
+use crate::cart::{banked_ram, banked_rom, store, Mapper};
+
+#[derive(Debug, Clone)]
+pub struct Huc1 {
+    /// 0xA000-0xBFFF is the IR port instead of RAM
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    ir: Arc<dyn IrEndpoint>,
+}
+
+impl Default for Huc1 {
+    fn default() -> Self {
+        Self {
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir: Arc::new(NoIr),
+        }
+    }
+}
+
+impl Mapper for Huc1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ir_mode = value & 0x0F == 0x0E,
+            0x2000..=0x3FFF => {
+                self.rom_bank = match value & 0x3F {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if self.ir_mode {
+            return ir_read(self.ir.as_ref());
+        }
+
+        banked_ram(ram, self.ram_bank as usize, address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ir_mode {
+            self.ir.set_led(value & 0x01 == 1);
+            return false;
+        }
+
+        banked_ram(ram, self.ram_bank as usize, address).is_some_and(|i| store(ram, i, value))
+    }
+
+    fn set_ir(&mut self, endpoint: Arc<dyn IrEndpoint>) {
+        self.ir = endpoint;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cart::ir::{IrEndpoint, IrLink};
+    use crate::cart::Cartridge;
+    use crate::testing::make_rom;
+
+    #[test]
+    fn test_ir() {
+        let (cart_port, fixture) = IrLink::pair();
+        let mut cart = Cartridge::new(make_rom("HUC1", 0xFF, 2, 3)).unwrap();
+        cart.set_ir(cart_port);
+
+        cart.write(0xA000, 0x42);
+        cart.write(0x0000, 0x0E);
+        assert_eq!(cart.read(0xA000), 0xC0);
+
+        fixture.set_led(true);
+        assert_eq!(cart.read(0xA000), 0xC1);
+
+        cart.write(0xA000, 0x01);
+        assert!(fixture.receiving());
+        cart.write(0xA000, 0x00);
+        assert!(!fixture.receiving());
+
+        cart.write(0x0000, 0x0A);
+        assert_eq!(cart.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn test_two_carts() {
+        let (a_port, b_port) = IrLink::pair();
+        let mut a = Cartridge::new(make_rom("HUC1", 0xFF, 2, 3)).unwrap();
+        let mut b = Cartridge::new(make_rom("HUC1", 0xFF, 2, 3)).unwrap();
+        a.set_ir(a_port);
+        b.set_ir(b_port);
+        a.write(0x0000, 0x0E);
+        b.write(0x0000, 0x0E);
+
+        a.write(0xA000, 0x01);
+        assert_eq!(b.read(0xA000), 0xC1);
+        assert_eq!(a.read(0xA000), 0xC0);
+    }
+}
@@ -0,0 +1,367 @@
+//! <https://gbdev.io/pandocs/HuC3.html>
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+
+use crate::cart::ir::{ir_read, IrEndpoint, NoIr};
+use crate::cart::rtc::{self, Clock, SystemClock};
+use crate::cart::{banked_ram, banked_rom, store, Mapper};
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+/// Size of the clock footer SameBoy appends to this mapper's save files.
+///
+/// It holds the host time as a u64, then the minute, day, alarm minute and
+/// alarm day counters as u16s and the alarm enable flag, all little endian.
+pub const HUC3_FOOTER_SIZE: usize = 17;
+
+/// What 0xA000-0xBFFF is connected to, set through 0x0000-0x1FFF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    RamReadOnly,
+    Ram,
+    RtcCommand,
+    RtcResponse,
+    RtcSemaphore,
+    Ir,
+    Unmapped,
+}
+
+impl From<u8> for Mode {
+    fn from(value: u8) -> Self {
+        match value & 0x0F {
+            0x0 => Self::RamReadOnly,
+            0xA => Self::Ram,
+            0xB => Self::RtcCommand,
+            0xC => Self::RtcResponse,
+            0xD => Self::RtcSemaphore,
+            0xE => Self::Ir,
+            _ => Self::Unmapped,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Huc3 {
+    mode: Mode,
+    rom_bank: u8,
+    ram_bank: u8,
+    clock: Arc<dyn Clock>,
+    /// Host time the clock was last brought up to date at
+    last_update: u64,
+    /// Seconds into the current minute, the RTC itself only counts minutes
+    seconds: u64,
+    /// Minute of the day, 0-1439
+    pub minutes: u16,
+    /// 12 bit day counter
+    pub days: u16,
+    /// 256 nibbles of RTC memory, accessed one at a time through commands
+    memory: [u8; 0x100],
+    address: u8,
+    last_command: u8,
+    response: u8,
+    /// The last tone the speaker was asked to play
+    tone: Option<u8>,
+    ir: Arc<dyn IrEndpoint>,
+}
+
+impl Default for Huc3 {
+    fn default() -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        Self {
+            mode: Mode::RamReadOnly,
+            rom_bank: 1,
+            ram_bank: 0,
+            last_update: clock.now(),
+            clock,
+            seconds: 0,
+            minutes: 0,
+            days: 0,
+            memory: [0; 0x100],
+            address: 0,
+            last_command: 0,
+            response: 0,
+            tone: None,
+            ir: Arc::new(NoIr),
+        }
+    }
+}
+
+impl Huc3 {
+    pub const fn tone(&self) -> Option<u8> {
+        self.tone
+    }
+
+    pub const fn last_update(&self) -> u64 {
+        self.last_update
+    }
+
+    /// Restores the clock as it was at host time `timestamp`, then catches up
+    pub fn restore(&mut self, minutes: u16, days: u16, timestamp: u64) {
+        self.minutes = minutes;
+        self.days = days;
+        self.seconds = 0;
+        self.last_update = timestamp;
+        self.update();
+    }
+
+    /// Catches the clock up with the host
+    pub fn update(&mut self) {
+        let now = self.clock.now();
+        let seconds = self.seconds + now.saturating_sub(self.last_update);
+        self.last_update = now;
+        self.seconds = seconds % 60;
+
+        let minutes = u64::from(self.minutes) + seconds / 60;
+        self.minutes = (minutes % MINUTES_PER_DAY) as u16;
+        self.days = ((u64::from(self.days) + minutes / MINUTES_PER_DAY) & 0xFFF) as u16;
+    }
+
+    fn command(&mut self, value: u8) {
+        let command = (value >> 4) & 0x07;
+        let argument = value & 0x0F;
+        self.last_command = command;
+
+        match command {
+            // Read and increment the address
+            0x1 => {
+                self.response = self.memory[usize::from(self.address)];
+                self.address = self.address.wrapping_add(1);
+            }
+            // Write and increment the address
+            0x3 => {
+                self.memory[usize::from(self.address)] = argument;
+                self.address = self.address.wrapping_add(1);
+            }
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | argument << 4,
+            0x6 => self.extended_command(argument),
+            _ => debug!("Unknown HuC3 RTC command {value:#04x}"),
+        }
+    }
+
+    fn extended_command(&mut self, argument: u8) {
+        match argument {
+            // Copy the current time into memory
+            0x0 => {
+                self.update();
+                let time = u32::from(self.minutes) | u32::from(self.days) << 12;
+                for (i, nibble) in self.memory[..6].iter_mut().enumerate() {
+                    *nibble = (time >> (i * 4)) as u8 & 0x0F;
+                }
+            }
+            // Set the current time from memory
+            0x1 => {
+                let time = self.memory[..6]
+                    .iter()
+                    .enumerate()
+                    .fold(0u32, |time, (i, nibble)| {
+                        time | u32::from(*nibble) << (i * 4)
+                    });
+                self.update();
+                self.seconds = 0;
+                self.minutes = (time & 0xFFF) as u16 % MINUTES_PER_DAY as u16;
+                self.days = (time >> 12) as u16 & 0xFFF;
+            }
+            // Status, always ready
+            0x2 => self.response = 0x1,
+            // Play the tone selected in memory
+            0xE => {
+                if self.memory[0x27] == 0x1 {
+                    let tone = self.memory[0x26];
+                    debug!("HuC3 tone {tone:#x}");
+                    self.tone = Some(tone);
+                }
+            }
+            _ => debug!("Unknown HuC3 extended RTC command {argument:#x}"),
+        }
+    }
+}
+
+impl Mapper for Huc3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.mode = Mode::from(value),
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        match self.mode {
+            Mode::RamReadOnly | Mode::Ram => {
+                banked_ram(ram, self.ram_bank as usize, address).map_or(0xFF, |i| ram[i])
+            }
+            Mode::RtcResponse => 0x80 | self.last_command << 4 | self.response,
+            Mode::RtcSemaphore => 0x01,
+            Mode::Ir => ir_read(self.ir.as_ref()),
+            Mode::RtcCommand | Mode::Unmapped => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        match self.mode {
+            Mode::Ram => {
+                return banked_ram(ram, self.ram_bank as usize, address)
+                    .is_some_and(|i| store(ram, i, value));
+            }
+            Mode::RtcCommand => self.command(value),
+            Mode::Ir => self.ir.set_led(value & 0x01 == 1),
+            Mode::RamReadOnly | Mode::RtcResponse | Mode::RtcSemaphore | Mode::Unmapped => {}
+        }
+        false
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.update();
+        self.last_update = clock.now();
+        self.clock = clock;
+    }
+
+    fn set_ir(&mut self, endpoint: Arc<dyn IrEndpoint>) {
+        self.ir = endpoint;
+    }
+
+    /// SameBoy's clock footer for this mapper
+    fn save_footer(&self) -> Vec<u8> {
+        // The footer has no seconds, so count from the start of the minute
+        let timestamp = self.last_update.saturating_sub(self.seconds);
+        let mut footer = Vec::with_capacity(HUC3_FOOTER_SIZE);
+        footer.extend(timestamp.to_le_bytes());
+        footer.extend(self.minutes.to_le_bytes());
+        footer.extend(self.days.to_le_bytes());
+        // The alarm isn't emulated: its minutes, days and enable flag
+        footer.extend([0; 5]);
+        footer
+    }
+
+    fn load_save_footer(&mut self, footer: &[u8]) {
+        let (minutes, days, timestamp) = if footer.len() == HUC3_FOOTER_SIZE {
+            (
+                u16::from_le_bytes([footer[8], footer[9]]),
+                u16::from_le_bytes([footer[10], footer[11]]),
+                u64::from_le_bytes(footer[..8].try_into().unwrap()),
+            )
+        } else {
+            // Older saves of ours, with the counters in the MBC3 clock footer
+            let Some(([minutes_low, minutes_high, days_low, days_high, _], _, timestamp)) =
+                rtc::decode_footer(footer)
+            else {
+                warn!("Ignoring {} byte clock footer", footer.len());
+                return;
+            };
+            (
+                u16::from_le_bytes([minutes_low, minutes_high]),
+                u16::from_le_bytes([days_low, days_high]),
+                timestamp,
+            )
+        };
+
+        self.restore(minutes % MINUTES_PER_DAY as u16, days & 0xFFF, timestamp);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::rtc::ManualClock;
+    use crate::cart::Cartridge;
+    use crate::testing::make_rom;
+
+    fn command(cart: &mut Cartridge, value: u8) -> u8 {
+        cart.write(0x0000, 0x0B);
+        cart.write(0xA000, value);
+        cart.write(0x0000, 0x0C);
+        cart.read(0xA000)
+    }
+
+    fn read_time(cart: &mut Cartridge) -> u32 {
+        command(cart, 0x60);
+        command(cart, 0x40);
+        command(cart, 0x50);
+        (0..6).fold(0, |time, i| {
+            time | u32::from(command(cart, 0x10) & 0x0F) << (i * 4)
+        })
+    }
+
+    #[test]
+    fn test_rtc() {
+        let clock = ManualClock::new(0);
+        let mut cart = Cartridge::new(make_rom("HUC3", 0xFE, 2, 3)).unwrap();
+        cart.set_clock(clock.clone());
+
+        clock.advance(59);
+        assert_eq!(read_time(&mut cart), 0);
+        clock.advance(1);
+        assert_eq!(read_time(&mut cart), 1);
+
+        clock.advance(2 * 24 * 60 * 60 + 60 * 60);
+        assert_eq!(read_time(&mut cart), 0x03D | 2 << 12);
+
+        // Set the clock to day 3, 00:10
+        command(&mut cart, 0x40);
+        command(&mut cart, 0x50);
+        for nibble in [0xA, 0, 0, 3, 0, 0] {
+            command(&mut cart, 0x30 | nibble);
+        }
+        command(&mut cart, 0x61);
+        assert_eq!(read_time(&mut cart), 0x00A | 3 << 12);
+
+        cart.write(0x0000, 0x0D);
+        assert_eq!(cart.read(0xA000), 0x01);
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut huc3 = Huc3::default();
+        huc3.write_rom(0x0000, 0x0B);
+        let mut ram = [];
+        for value in [0x46, 0x52, 0x33, 0x31, 0x6E] {
+            huc3.write_ram(&mut ram, 0xA000, value);
+        }
+        assert_eq!(huc3.tone(), Some(0x3));
+    }
+
+    #[test]
+    fn test_ram_modes() {
+        let mut cart = Cartridge::new(make_rom("HUC3", 0xFE, 2, 3)).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x42);
+        cart.write(0x0000, 0x00);
+        cart.write(0xA000, 0x24);
+        assert_eq!(cart.read(0xA000), 0x42);
+
+        cart.write(0x0000, 0x0E);
+        assert_eq!(cart.read(0xA000), 0xC0);
+    }
+
+    #[test]
+    fn test_save_footer() {
+        let clock = ManualClock::new(1000);
+        let mut huc3 = Huc3::default();
+        huc3.set_clock(clock.clone());
+        huc3.restore(0x123, 0x45, 1000);
+        clock.advance(30);
+        huc3.update();
+
+        let footer = huc3.save_footer();
+        assert_eq!(
+            footer,
+            [0xE8, 0x03, 0, 0, 0, 0, 0, 0, 0x23, 0x01, 0x45, 0x00, 0, 0, 0, 0, 0]
+        );
+
+        // Two days and an hour later
+        clock.advance(2 * 24 * 60 * 60 + 60 * 60);
+        let mut huc3 = Huc3::default();
+        huc3.set_clock(clock);
+        huc3.load_save_footer(&footer);
+        assert_eq!((huc3.minutes, huc3.days), (0x123 + 60, 0x47));
+    }
+}
@@ -0,0 +1,62 @@
+//! Infrared ports found on some cartridges, next to the RAM
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The other side of a cartridge's infrared LED and receiver
+pub trait IrEndpoint: fmt::Debug + Send + Sync {
+    /// Called whenever the cartridge turns its LED on or off
+    fn set_led(&self, on: bool);
+    /// Whether the cartridge's receiver currently sees light
+    fn receiving(&self) -> bool;
+}
+
+/// Nothing in front of the cartridge, the LED goes nowhere and nothing is received
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoIr;
+
+impl IrEndpoint for NoIr {
+    fn set_led(&self, _on: bool) {}
+
+    fn receiving(&self) -> bool {
+        false
+    }
+}
+
+/// One end of a pair of ports facing each other, each receiving the other's LED.
+///
+/// Plug one into each emulator instance, or keep one in a test to drive the cartridge.
+#[derive(Debug, Clone, Default)]
+pub struct IrLink {
+    led: Arc<AtomicBool>,
+    remote: Arc<AtomicBool>,
+}
+
+impl IrLink {
+    pub fn pair() -> (Arc<Self>, Arc<Self>) {
+        let a = Arc::new(AtomicBool::new(false));
+        let b = Arc::new(AtomicBool::new(false));
+        (
+            Arc::new(Self {
+                led: Arc::clone(&a),
+                remote: Arc::clone(&b),
+            }),
+            Arc::new(Self { led: b, remote: a }),
+        )
+    }
+}
+
+impl IrEndpoint for IrLink {
+    fn set_led(&self, on: bool) {
+        self.led.store(on, Ordering::Relaxed);
+    }
+
+    fn receiving(&self) -> bool {
+        self.remote.load(Ordering::Relaxed)
+    }
+}
+
+/// The value the Hudson carts return from the RAM window in IR mode
+pub fn ir_read(endpoint: &dyn IrEndpoint) -> u8 {
+    0xC0 | u8::from(endpoint.receiving())
+}
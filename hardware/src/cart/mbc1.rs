@@ -0,0 +1,218 @@
+//! <https://gbdev.io/pandocs/MBC1.html>
+use crate::cart::{banked_ram, banked_rom, store, Mapper};
+use crate::NINTENDO_HEADER;
+
+#[derive(Debug, Clone, Default)]
+pub struct Mbc1 {
+    ram_enabled: bool,
+    /// 5 bit ROM bank register at 0x2000-0x3FFF
+    bank1: u8,
+    /// 2 bit register at 0x4000-0x5FFF, upper ROM bank bits or the RAM bank
+    bank2: u8,
+    /// Banking mode 1 also applies `bank2` to 0x0000-0x3FFF and to RAM
+    mode: bool,
+    /// MBC1M wiring, where `bank2` is shifted by 4 instead of 5
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8]) -> Self {
+        Self {
+            bank1: 1,
+            multicart: is_multicart(rom),
+            ..Default::default()
+        }
+    }
+
+    /// Forces MBC1M wiring for multicarts [`is_multicart`] doesn't recognize
+    pub fn multicart() -> Self {
+        Self {
+            bank1: 1,
+            multicart: true,
+            ..Default::default()
+        }
+    }
+
+    pub const fn is_multicart(&self) -> bool {
+        self.multicart
+    }
+
+    const fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    const fn low_bank(&self) -> usize {
+        if self.mode {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    const fn high_bank(&self) -> usize {
+        let bank1 = if self.multicart {
+            self.bank1 & 0x0F
+        } else {
+            self.bank1
+        };
+
+        ((self.bank2 << self.bank2_shift()) | bank1) as usize
+    }
+
+    const fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, self.low_bank(), address),
+            _ => banked_rom(rom, self.high_bank(), address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // The zero check happens on all 5 bits, even when fewer are wired up
+                self.bank1 = match value & 0x1F {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 == 1,
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        banked_ram(ram, self.ram_bank(), address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        banked_ram(ram, self.ram_bank(), address).is_some_and(|i| store(ram, i, value))
+    }
+}
+
+/// MBC1M carts are 1 MiB compilations of 256 KiB games, each with its own header.
+/// They can only be told apart from a regular MBC1 by finding a second logo.
+fn is_multicart(rom: &[u8]) -> bool {
+    const LOGO: usize = 0x0104;
+    const GAME_SIZE: usize = 0x0004_0000;
+
+    rom.len() == 0x0010_0000
+        && (1..4).any(|game| {
+            let start = game * GAME_SIZE + LOGO;
+            rom[start..start + NINTENDO_HEADER.len()] == NINTENDO_HEADER
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::{Cartridge, ROM_BANK_SIZE};
+    use crate::testing::make_rom;
+
+    /// Marks the first byte of every bank with its number
+    fn numbered_rom(rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = make_rom("MBC1", 0x03, rom_size, ram_size);
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate().skip(1) {
+            chunk[0] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut cart = Cartridge::new(numbered_rom(4, 3)).unwrap();
+        assert_eq!(cart.read(0x4000), 1);
+
+        cart.write(0x2000, 0x05);
+        assert_eq!(cart.read(0x4000), 5);
+
+        // 0 is translated to 1
+        cart.write(0x2000, 0x00);
+        assert_eq!(cart.read(0x4000), 1);
+
+        // Only the 5 lower bits are checked for 0, so 0x20 also maps bank 1
+        cart.write(0x2000, 0x20);
+        assert_eq!(cart.read(0x4000), 1);
+
+        cart.write(0x2000, 0x1F);
+        assert_eq!(cart.read(0x4000), 0x1F);
+    }
+
+    #[test]
+    fn test_mode1() {
+        let mut cart = Cartridge::new(numbered_rom(6, 3)).unwrap();
+        cart.write(0x2000, 0x02);
+        cart.write(0x4000, 0x01);
+        assert_eq!(cart.read(0x4000), 0x22);
+        assert_eq!(cart.read(0x0000), 0x00);
+
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0x0000), 0x20);
+
+        // RAM is banked by the secondary register in mode 1
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x11);
+        cart.write(0x4000, 0x02);
+        cart.write(0xA000, 0x22);
+        assert_eq!(cart.ram()[0x2000], 0x11);
+        assert_eq!(cart.ram()[0x4000], 0x22);
+
+        cart.write(0x6000, 0x00);
+        assert_eq!(cart.read(0xA000), cart.ram()[0]);
+    }
+
+    #[test]
+    fn test_ram_enable() {
+        let mut cart = Cartridge::new(numbered_rom(1, 2)).unwrap();
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0xFF);
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0x42);
+
+        cart.write(0x0000, 0x00);
+        assert_eq!(cart.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut rom = numbered_rom(5, 0);
+        rom[0x4_0104..0x4_0134].copy_from_slice(&NINTENDO_HEADER);
+        let mbc = Mbc1::new(&rom);
+        assert!(mbc.is_multicart());
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.write(0x4000, 0x01);
+        cart.write(0x2000, 0x12);
+        // bank2 << 4 | (bank1 & 0xF)
+        assert_eq!(cart.read(0x4000), 0x12);
+
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0x0000), 0x10);
+
+        assert!(!Mbc1::new(&numbered_rom(5, 0)).is_multicart());
+    }
+}
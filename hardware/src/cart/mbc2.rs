@@ -0,0 +1,98 @@
+//! <https://gbdev.io/pandocs/MBC2.html>
+use crate::cart::{banked_rom, store, Mapper};
+
+/// 512 half-bytes of RAM built into the controller
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+#[derive(Debug, Clone)]
+pub struct Mbc2 {
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Default for Mbc2 {
+    fn default() -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        // Both registers share 0x0000-0x3FFF, bit 8 of the address picks one
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => {
+                self.rom_bank = match value & 0x0F {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        // Only the lower nibble is wired up, the upper one floats high
+        0xF0 | ram[address as usize % MBC2_RAM_SIZE]
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        self.ram_enabled && store(ram, address as usize % MBC2_RAM_SIZE, value & 0x0F)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cart::Cartridge;
+    use crate::testing::make_rom;
+
+    #[test]
+    fn test_registers() {
+        let mut rom = make_rom("MBC2", 0x06, 3, 0);
+        rom[0x3 * 0x4000] = 0x33;
+        let mut cart = Cartridge::new(rom).unwrap();
+
+        // Bit 8 clear enables RAM, even in the upper half of the register range
+        cart.write(0x3000, 0x0A);
+        cart.write(0xA000, 0x12);
+        assert_eq!(cart.read(0xA000), 0xF2);
+
+        // Bit 8 set selects a ROM bank
+        cart.write(0x2100, 0x03);
+        assert_eq!(cart.read(0x4000), 0x33);
+        cart.write(0x0100, 0x00);
+        assert_eq!(cart.read(0x4000), 0x00);
+        assert_eq!(cart.read(0x4000), cart.read(0x0000));
+
+        cart.write(0x0000, 0x00);
+        assert_eq!(cart.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_ram_echo() {
+        let mut cart = Cartridge::new(make_rom("MBC2", 0x06, 0, 0)).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA1FF, 0xFF);
+        assert_eq!(cart.read(0xA3FF), 0xFF);
+        assert_eq!(cart.read(0xBFFF), 0xFF);
+
+        cart.write(0xB000, 0x05);
+        assert_eq!(cart.read(0xA000), 0xF5);
+        assert_eq!(cart.ram().len(), 512);
+        assert_eq!(cart.battery_ram(), Some(cart.ram()));
+    }
+}
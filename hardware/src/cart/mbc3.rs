@@ -0,0 +1,186 @@
+//! <https://gbdev.io/pandocs/MBC3.html>
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::cart::rtc::{self, Clock, Rtc};
+use crate::cart::{banked_ram, banked_rom, store, Mapper};
+
+#[derive(Debug, Clone)]
+pub struct Mbc3 {
+    /// Enables both RAM and the clock registers
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// 0x00-0x07 selects a RAM bank, 0x08-0x0C a clock register
+    ram_select: u8,
+    /// The last value written to 0x6000-0x7FFF, the clock latches on a 0 -> 1 write
+    latch: u8,
+    rtc: Option<Rtc>,
+}
+
+impl Mbc3 {
+    pub fn new(has_rtc: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch: 0xFF,
+            rtc: has_rtc.then(Rtc::default),
+        }
+    }
+
+    pub const fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    pub const fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    const fn rtc_selected(&self) -> bool {
+        matches!(self.ram_select, 0x08..=0x0C)
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = match value & 0x7F {
+                    0 => 1,
+                    bank => bank,
+                };
+            }
+            0x4000..=0x5FFF => self.ram_select = value & 0x0F,
+            _ => {
+                if self.latch == 0 && value == 1 {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch = value;
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        if self.rtc_selected() {
+            return self
+                .rtc
+                .as_ref()
+                .map_or(0xFF, |rtc| rtc.read(self.ram_select));
+        }
+
+        banked_ram(ram, self.ram_select as usize, address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        if self.rtc_selected() {
+            if let Some(rtc) = &mut self.rtc {
+                rtc.write(self.ram_select, value);
+            }
+            return false;
+        }
+
+        banked_ram(ram, self.ram_select as usize, address).is_some_and(|i| store(ram, i, value))
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    fn save_footer(&self) -> Vec<u8> {
+        self.rtc.as_ref().map_or_else(Vec::new, |rtc| {
+            rtc::encode_footer(rtc.registers(), rtc.latched(), rtc.last_update())
+        })
+    }
+
+    fn load_save_footer(&mut self, footer: &[u8]) {
+        let Some(rtc) = &mut self.rtc else {
+            return;
+        };
+
+        let Some((registers, latched, timestamp)) = rtc::decode_footer(footer) else {
+            warn!("Ignoring {} byte clock footer", footer.len());
+            return;
+        };
+        rtc.restore(registers, latched, timestamp);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cart::rtc::ManualClock;
+    use crate::cart::Cartridge;
+    use crate::testing::make_rom;
+
+    #[test]
+    fn test_banking() {
+        let mut rom = make_rom("MBC3", 0x13, 6, 3);
+        rom[0x7F * 0x4000] = 0x7F;
+        let mut cart = Cartridge::new(rom).unwrap();
+
+        cart.write(0x2000, 0xFF);
+        assert_eq!(cart.read(0x4000), 0x7F);
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x03);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.ram()[3 * 0x2000], 0x42);
+
+        // No clock on this cartridge type
+        cart.write(0x4000, 0x08);
+        assert_eq!(cart.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_rtc_latch() {
+        let clock = ManualClock::new(0);
+        let mut cart = Cartridge::new(make_rom("MBC3", 0x10, 0, 3)).unwrap();
+        cart.set_clock(clock.clone());
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x08);
+        cart.write(0x6000, 0x00);
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0xA000), 0);
+
+        // Without a new latch sequence the game keeps reading the old value
+        clock.advance(30);
+        assert_eq!(cart.read(0xA000), 0);
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0xA000), 0);
+
+        cart.write(0x6000, 0x00);
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0xA000), 30);
+
+        // Writing the registers sets the live time
+        cart.write(0x4000, 0x0A);
+        cart.write(0xA000, 23);
+        clock.advance(60 * 60);
+        cart.write(0x6000, 0x00);
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read(0xA000), 0);
+        cart.write(0x4000, 0x0B);
+        assert_eq!(cart.read(0xA000), 1);
+    }
+}
@@ -0,0 +1,119 @@
+//! <https://gbdev.io/pandocs/MBC5.html>
+use crate::cart::{banked_ram, banked_rom, store, Mapper};
+
+#[derive(Debug, Clone)]
+pub struct Mbc5 {
+    ram_enabled: bool,
+    /// 9 bit ROM bank, unlike the older controllers 0 is not translated to 1
+    rom_bank: u16,
+    ram_bank: u8,
+    /// Rumble carts wire bit 3 of the RAM bank register to the motor
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub const fn new(has_rumble: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | u16::from(value),
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | (u16::from(value & 0x01) << 8);
+            }
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.rumble = value & 0x08 != 0;
+                self.ram_bank = value & 0x07;
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        banked_ram(ram, self.ram_bank as usize, address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        banked_ram(ram, self.ram_bank as usize, address).is_some_and(|i| store(ram, i, value))
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cart::Cartridge;
+    use crate::testing::make_rom;
+
+    #[test]
+    fn test_rom_banking() {
+        let mut rom = make_rom("MBC5", 0x19, 8, 0);
+        rom[0x1AB * 0x4000] = 0xAB;
+        let mut cart = Cartridge::new(rom).unwrap();
+        assert_eq!(cart.rom().len(), 8 * 1024 * 1024);
+
+        cart.write(0x2000, 0xAB);
+        cart.write(0x3000, 0x01);
+        assert_eq!(cart.read(0x4000), 0xAB);
+
+        // Bank 0 can be mapped into the switchable area
+        cart.write(0x2000, 0x00);
+        cart.write(0x3000, 0x00);
+        assert_eq!(cart.read(0x4104), cart.read(0x0104));
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut cart = Cartridge::new(make_rom("MBC5", 0x1B, 0, 4)).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x0F);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.ram()[15 * 0x2000], 0x42);
+        assert!(!cart.rumble());
+    }
+
+    #[test]
+    fn test_rumble() {
+        let mut cart = Cartridge::new(make_rom("MBC5", 0x1E, 0, 3)).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x0B);
+        assert!(cart.rumble());
+
+        // Bit 3 does not take part in RAM banking
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.ram()[3 * 0x2000], 0x42);
+
+        cart.write(0x4000, 0x03);
+        assert!(!cart.rumble());
+        assert_eq!(cart.read(0xA000), 0x42);
+    }
+}
@@ -0,0 +1,303 @@
+//! <https://gbdev.io/pandocs/MBC6.html>
+use tracing::debug;
+
+use crate::cart::{store, Mapper};
+
+/// 1 MiB of flash memory, a Macronix MX29F008
+pub const FLASH_SIZE: usize = 0x0010_0000;
+/// Both ROM/flash and RAM are switched in halves of the usual bank size
+const ROM_WINDOW: usize = 0x2000;
+const RAM_WINDOW: usize = 0x1000;
+/// Sector erase clears one of the 64 KiB main sectors
+const SECTOR_SIZE: usize = 0x0001_0000;
+
+#[derive(Debug, Clone, Default)]
+pub struct Mbc6 {
+    ram_enabled: bool,
+    ram_bank: [u8; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    rom_bank: [u8; 2],
+    /// Whether each ROM window maps flash instead of ROM
+    flash_selected: [bool; 2],
+    flash: Flash,
+}
+
+impl Mbc6 {
+    pub fn flash(&self) -> &[u8] {
+        &self.flash.data
+    }
+
+    /// Window 0 is 0x4000-0x5FFF or 0xA000-0xAFFF, window 1 the half after it
+    fn rom_offset(&self, address: u16) -> (usize, usize) {
+        let window = usize::from(address >= 0x6000);
+        let offset = usize::from(self.rom_bank[window]) * ROM_WINDOW
+            + (usize::from(address) & (ROM_WINDOW - 1));
+        (window, offset)
+    }
+
+    fn ram_offset(&self, ram: &[u8], address: u16) -> Option<usize> {
+        if ram.is_empty() {
+            return None;
+        }
+
+        let window = usize::from(address >= 0xB000);
+        let offset = usize::from(self.ram_bank[window]) * RAM_WINDOW
+            + (usize::from(address) & (RAM_WINDOW - 1));
+        Some(offset % ram.len())
+    }
+}
+
+impl Mapper for Mbc6 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        if address < 0x4000 {
+            return rom.get(usize::from(address)).copied().unwrap_or(0xFF);
+        }
+
+        let (window, offset) = self.rom_offset(address);
+        if self.flash_selected[window] {
+            if !self.flash_enabled {
+                return 0xFF;
+            }
+            return self.flash.read(offset % FLASH_SIZE);
+        }
+
+        if rom.is_empty() {
+            return 0xFF;
+        }
+        rom[offset % rom.len()]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_bank[0] = value & 0x07,
+            0x0800..=0x0BFF => self.ram_bank[1] = value & 0x07,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 0x01 == 1,
+            0x1000 => self.flash_write_enabled = value & 0x01 == 1,
+            0x2000..=0x27FF => self.rom_bank[0] = value & 0x7F,
+            0x2800..=0x2FFF => self.flash_selected[0] = value == 0x08,
+            0x3000..=0x37FF => self.rom_bank[1] = value & 0x7F,
+            0x3800..=0x3FFF => self.flash_selected[1] = value == 0x08,
+            0x4000..=0x7FFF => {
+                let (window, offset) = self.rom_offset(address);
+                if self.flash_selected[window] && self.flash_enabled {
+                    self.flash
+                        .write(offset % FLASH_SIZE, value, self.flash_write_enabled);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        self.ram_offset(ram, address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        self.ram_offset(ram, address)
+            .is_some_and(|i| store(ram, i, value))
+    }
+
+    fn save_footer(&self) -> Vec<u8> {
+        self.flash.data.clone()
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.flash.dirty)
+    }
+
+    fn load_save_footer(&mut self, footer: &[u8]) {
+        let len = footer.len().min(FLASH_SIZE);
+        self.flash.data[..len].copy_from_slice(&footer[..len]);
+    }
+}
+
+/// Progress through the JEDEC style unlock sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Ready,
+    Unlock1,
+    Unlock2,
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    Program,
+}
+
+#[derive(Debug, Clone)]
+struct Flash {
+    data: Vec<u8>,
+    state: FlashState,
+    /// Reads return the manufacturer and device IDs
+    id_mode: bool,
+    /// Programmed or erased since the last save
+    dirty: bool,
+}
+
+impl Default for Flash {
+    fn default() -> Self {
+        Self {
+            data: vec![0xFF; FLASH_SIZE],
+            state: FlashState::Ready,
+            id_mode: false,
+            dirty: false,
+        }
+    }
+}
+
+impl Flash {
+    fn read(&self, offset: usize) -> u8 {
+        if self.id_mode {
+            // Macronix, MX29F008
+            return if offset & 1 == 0 { 0xC2 } else { 0x81 };
+        }
+
+        self.data[offset]
+    }
+
+    fn write(&mut self, offset: usize, value: u8, write_enabled: bool) {
+        use FlashState::*;
+
+        if value == 0xF0 {
+            self.state = Ready;
+            self.id_mode = false;
+            return;
+        }
+
+        let command = offset & 0x7FFF == 0x5555;
+        let unlock = offset & 0x7FFF == 0x2AAA;
+        self.state = match (self.state, value) {
+            (Ready, 0xAA) if command => Unlock1,
+            (Unlock1, 0x55) if unlock => Unlock2,
+            (Unlock2, 0x80) if command => Erase,
+            (Unlock2, 0xA0) if command => Program,
+            (Unlock2, 0x90) if command => {
+                self.id_mode = true;
+                Ready
+            }
+            (Erase, 0xAA) if command => EraseUnlock1,
+            (EraseUnlock1, 0x55) if unlock => EraseUnlock2,
+            (EraseUnlock2, 0x10) if command => {
+                if write_enabled {
+                    debug!("MBC6 flash chip erase");
+                    self.data.fill(0xFF);
+                    self.dirty = true;
+                }
+                Ready
+            }
+            (EraseUnlock2, 0x30) => {
+                if write_enabled {
+                    let sector = offset / SECTOR_SIZE * SECTOR_SIZE;
+                    debug!("MBC6 flash sector erase at {sector:#07x}");
+                    self.data[sector..sector + SECTOR_SIZE].fill(0xFF);
+                    self.dirty = true;
+                }
+                Ready
+            }
+            (Program, _) => {
+                // Programming can only clear bits, erasing sets them again
+                if write_enabled {
+                    self.data[offset] &= value;
+                    self.dirty = true;
+                }
+                Ready
+            }
+            _ => Ready,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writes to the flash through window 0, which covers `bank`
+    fn flash_write(mbc: &mut Mbc6, offset: usize, value: u8) {
+        mbc.write_rom(0x2000, (offset / ROM_WINDOW) as u8);
+        mbc.write_rom(0x4000 + (offset % ROM_WINDOW) as u16, value);
+    }
+
+    fn unlocked() -> Mbc6 {
+        let mut mbc = Mbc6::default();
+        mbc.write_rom(0x0C00, 0x01);
+        mbc.write_rom(0x1000, 0x01);
+        mbc.write_rom(0x2800, 0x08);
+        mbc
+    }
+
+    #[test]
+    fn test_program_and_erase() {
+        let mut mbc = unlocked();
+        let rom = [];
+
+        for (offset, value) in [
+            (0x5555, 0xAA),
+            (0x2AAA, 0x55),
+            (0x5555, 0xA0),
+            (0x12345, 0x42),
+        ] {
+            flash_write(&mut mbc, offset, value);
+        }
+        assert_eq!(mbc.flash()[0x12345], 0x42);
+        assert!(mbc.take_dirty());
+        assert!(!mbc.take_dirty());
+        mbc.write_rom(0x2000, 0x09);
+        assert_eq!(mbc.read_rom(&rom, 0x4345), 0x42);
+
+        // Sector erase
+        for (offset, value) in [
+            (0x5555, 0xAA),
+            (0x2AAA, 0x55),
+            (0x5555, 0x80),
+            (0x5555, 0xAA),
+            (0x2AAA, 0x55),
+            (0x10000, 0x30),
+        ] {
+            flash_write(&mut mbc, offset, value);
+        }
+        assert_eq!(mbc.flash()[0x12345], 0xFF);
+
+        // Programming without write enable is ignored
+        mbc.write_rom(0x1000, 0x00);
+        for (offset, value) in [
+            (0x5555, 0xAA),
+            (0x2AAA, 0x55),
+            (0x5555, 0xA0),
+            (0x0100, 0x00),
+        ] {
+            flash_write(&mut mbc, offset, value);
+        }
+        assert_eq!(mbc.flash()[0x0100], 0xFF);
+        assert_eq!(mbc.save_footer().len(), FLASH_SIZE);
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut mbc = Mbc6::default();
+        let mut rom = vec![0; 0x10_0000];
+        rom[3 * ROM_WINDOW] = 0x03;
+        rom[5 * ROM_WINDOW + 1] = 0x05;
+        mbc.write_rom(0x2000, 0x03);
+        mbc.write_rom(0x3000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x03);
+        assert_eq!(mbc.read_rom(&rom, 0x6001), 0x05);
+
+        let mut ram = vec![0; 0x8000];
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x0400, 0x01);
+        mbc.write_rom(0x0800, 0x07);
+        mbc.write_ram(&mut ram, 0xA000, 0x11);
+        mbc.write_ram(&mut ram, 0xB000, 0x77);
+        assert_eq!(ram[0x1000], 0x11);
+        assert_eq!(ram[0x7000], 0x77);
+    }
+}
@@ -0,0 +1,380 @@
+//! <https://gbdev.io/pandocs/MBC7.html>
+use crate::cart::{banked_rom, Mapper};
+
+/// The 93LC56 holds 128 16 bit words, stored in the cartridge RAM buffer
+pub const EEPROM_SIZE: usize = 0x100;
+
+/// Accelerometer reading when the console is held flat
+const ACCEL_CENTER: f32 = 33232.0;
+/// Change in the accelerometer reading for a tilt of 1g
+const ACCEL_GRAVITY: f32 = 112.0;
+
+#[derive(Debug, Clone)]
+pub struct Mbc7 {
+    ram_enabled: [bool; 2],
+    rom_bank: u8,
+    /// Host tilt in g, positive x is right and positive y is down
+    tilt: (f32, f32),
+    latched: (u16, u16),
+    /// Latching only works after the latched values have been erased
+    latch_ready: bool,
+    eeprom: Eeprom,
+}
+
+impl Default for Mbc7 {
+    fn default() -> Self {
+        Self {
+            ram_enabled: [false; 2],
+            rom_bank: 1,
+            tilt: (0.0, 0.0),
+            latched: (0x8000, 0x8000),
+            latch_ready: false,
+            eeprom: Eeprom::default(),
+        }
+    }
+}
+
+impl Mbc7 {
+    #[allow(clippy::cast_sign_loss)]
+    fn accelerometer(&self) -> (u16, u16) {
+        let axis = |g: f32| {
+            g.mul_add(ACCEL_GRAVITY, ACCEL_CENTER)
+                .clamp(0.0, f32::from(u16::MAX)) as u16
+        };
+        // The sensor reads lower values when tilted right or down
+        (axis(-self.tilt.0), axis(-self.tilt.1))
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled[0] = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled[1] = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, _ram: &[u8], address: u16) -> u8 {
+        if self.ram_enabled != [true; 2] || address >= 0xB000 {
+            return 0xFF;
+        }
+
+        let [x_low, x_high] = self.latched.0.to_le_bytes();
+        let [y_low, y_high] = self.latched.1.to_le_bytes();
+        match (address >> 4) & 0x0F {
+            0x2 => x_low,
+            0x3 => x_high,
+            0x4 => y_low,
+            0x5 => y_high,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if self.ram_enabled != [true; 2] || address >= 0xB000 {
+            return false;
+        }
+
+        match (address >> 4) & 0x0F {
+            0x0 if value == 0x55 => {
+                self.latched = (0x8000, 0x8000);
+                self.latch_ready = true;
+            }
+            0x1 if value == 0xAA && self.latch_ready => {
+                self.latched = self.accelerometer();
+                self.latch_ready = false;
+            }
+            0x8 => return self.eeprom.write(ram, value),
+            _ => {}
+        }
+        false
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    /// Waiting for a start bit
+    Idle,
+    /// Shifting in the 2 bit opcode and 8 bit address
+    Command,
+    /// Shifting out words, starting at `address`
+    Read,
+    /// Shifting in a word for `address`, or every address
+    Write { all: bool },
+    /// Command finished, waiting for chip select to drop
+    Done,
+}
+
+/// A 93LC56 serial EEPROM in 16 bit mode, bit-banged through 0xA080
+///
+/// <https://gbdev.io/pandocs/MBC7.html#ax8x--eeprom>
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone)]
+struct Eeprom {
+    cs: bool,
+    clk: bool,
+    data_out: bool,
+    write_enabled: bool,
+    state: EepromState,
+    shift: u16,
+    bits: u8,
+    address: u8,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self {
+            cs: false,
+            clk: false,
+            data_out: true,
+            write_enabled: false,
+            state: EepromState::Idle,
+            shift: 0,
+            bits: 0,
+            address: 0,
+        }
+    }
+}
+
+impl Eeprom {
+    fn read(&self) -> u8 {
+        u8::from(self.cs) << 7 | u8::from(self.clk) << 6 | u8::from(self.data_out)
+    }
+
+    /// Drives the EEPROM's pins, returning whether that changed its contents
+    fn write(&mut self, ram: &mut [u8], value: u8) -> bool {
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        let data_in = value & 0x02 != 0;
+        let rising = clk && !self.clk;
+        self.cs = cs;
+        self.clk = clk;
+
+        if !cs {
+            self.state = EepromState::Idle;
+            self.data_out = true;
+            return false;
+        }
+
+        rising && self.clock(ram, data_in)
+    }
+
+    fn shift_in(&mut self, bit: bool) {
+        self.shift = self.shift << 1 | u16::from(bit);
+        self.bits += 1;
+    }
+
+    fn clock(&mut self, ram: &mut [u8], data_in: bool) -> bool {
+        let mut changed = false;
+        match self.state {
+            EepromState::Idle if data_in => {
+                self.state = EepromState::Command;
+                self.shift = 0;
+                self.bits = 0;
+            }
+            EepromState::Idle | EepromState::Done => {}
+            EepromState::Command => {
+                self.shift_in(data_in);
+                if self.bits == 10 {
+                    changed = self.command(ram);
+                }
+            }
+            EepromState::Read => {
+                self.data_out = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+                if self.bits == 16 {
+                    // Sequential reads continue with the next word
+                    self.address = (self.address + 1) & 0x7F;
+                    self.shift = read_word(ram, self.address);
+                    self.bits = 0;
+                }
+            }
+            EepromState::Write { all } => {
+                self.shift_in(data_in);
+                if self.bits == 16 {
+                    if self.write_enabled {
+                        if all {
+                            for address in 0..0x80 {
+                                changed |= write_word(ram, address, self.shift);
+                            }
+                        } else {
+                            changed = write_word(ram, self.address, self.shift);
+                        }
+                    }
+                    self.data_out = true;
+                    self.state = EepromState::Done;
+                }
+            }
+        }
+        changed
+    }
+
+    fn command(&mut self, ram: &mut [u8]) -> bool {
+        let mut changed = false;
+        let opcode = (self.shift >> 8) & 0x03;
+        // The extended commands use the upper two address bits
+        let extended = (self.shift >> 6) & 0x03;
+        self.address = (self.shift & 0x7F) as u8;
+        self.shift = 0;
+        self.bits = 0;
+        self.state = EepromState::Done;
+
+        match opcode {
+            // READ, a dummy 0 is output before the data
+            0b10 => {
+                self.shift = read_word(ram, self.address);
+                self.data_out = false;
+                self.state = EepromState::Read;
+            }
+            // WRITE
+            0b01 => self.state = EepromState::Write { all: false },
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    changed = write_word(ram, self.address, 0xFFFF);
+                }
+                self.data_out = true;
+            }
+            _ => match extended {
+                // EWEN
+                0b11 => self.write_enabled = true,
+                // EWDS
+                0b00 => self.write_enabled = false,
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        changed = ram.iter().any(|&byte| byte != 0xFF);
+                        ram.fill(0xFF);
+                    }
+                    self.data_out = true;
+                }
+                // WRAL
+                _ => self.state = EepromState::Write { all: true },
+            },
+        }
+        changed
+    }
+}
+
+fn read_word(ram: &[u8], address: u8) -> u16 {
+    let i = usize::from(address) * 2;
+    u16::from_le_bytes([ram[i], ram[i + 1]])
+}
+
+/// Returns whether the word changed
+fn write_word(ram: &mut [u8], address: u8, value: u16) -> bool {
+    let changed = read_word(ram, address) != value;
+    let i = usize::from(address) * 2;
+    ram[i..i + 2].copy_from_slice(&value.to_le_bytes());
+    changed
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cart::Cartridge;
+    use crate::testing::make_rom;
+
+    // Start bit followed by the opcode
+    const READ: u16 = 0b110;
+    const WRITE: u16 = 0b101;
+    const EXTENDED: u16 = 0b100;
+
+    fn enabled_cart() -> Cartridge {
+        let mut cart = Cartridge::new(make_rom("KIRBY", 0x22, 4, 0)).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x40);
+        cart
+    }
+
+    /// Clocks `count` bits of `value` into the EEPROM, most significant first
+    fn send(cart: &mut Cartridge, value: u16, count: u8) {
+        for i in (0..count).rev() {
+            let di = u8::from(value >> i & 1 == 1) << 1;
+            cart.write(0xA080, 0x80 | di);
+            cart.write(0xA080, 0xC0 | di);
+        }
+    }
+
+    fn receive(cart: &mut Cartridge) -> u16 {
+        (0..16).fold(0, |word, _| {
+            cart.write(0xA080, 0x80);
+            cart.write(0xA080, 0xC0);
+            word << 1 | u16::from(cart.read(0xA080) & 1)
+        })
+    }
+
+    fn deselect(cart: &mut Cartridge) {
+        cart.write(0xA080, 0x00);
+    }
+
+    #[test]
+    fn test_accelerometer() {
+        let mut cart = enabled_cart();
+        cart.set_tilt(0.0, 0.0);
+
+        cart.write(0xA000, 0x55);
+        assert_eq!(cart.read(0xA020), 0x00);
+        assert_eq!(cart.read(0xA030), 0x80);
+
+        cart.write(0xA010, 0xAA);
+        assert_eq!(cart.read(0xA020), 0xD0);
+        assert_eq!(cart.read(0xA030), 0x81);
+
+        // The latched value only changes after another erase and latch
+        cart.set_tilt(1.0, -1.0);
+        cart.write(0xA010, 0xAA);
+        assert_eq!(cart.read(0xA020), 0xD0);
+
+        cart.write(0xA000, 0x55);
+        cart.write(0xA010, 0xAA);
+        let x = u16::from_le_bytes([cart.read(0xA020), cart.read(0xA030)]);
+        let y = u16::from_le_bytes([cart.read(0xA040), cart.read(0xA050)]);
+        assert_eq!(x, 0x81D0 - 0x70);
+        assert_eq!(y, 0x81D0 + 0x70);
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut cart = enabled_cart();
+
+        // Writes are ignored until EWEN
+        send(&mut cart, WRITE, 3);
+        send(&mut cart, 0x05, 8);
+        send(&mut cart, 0x1234, 16);
+        deselect(&mut cart);
+        assert_eq!(cart.ram()[10..12], [0x00, 0x00]);
+
+        send(&mut cart, EXTENDED, 3);
+        send(&mut cart, 0xC0, 8);
+        deselect(&mut cart);
+        send(&mut cart, WRITE, 3);
+        send(&mut cart, 0x05, 8);
+        send(&mut cart, 0x1234, 16);
+        deselect(&mut cart);
+        assert_eq!(cart.ram()[10..12], [0x34, 0x12]);
+
+        send(&mut cart, READ, 3);
+        send(&mut cart, 0x05, 8);
+        assert_eq!(cart.read(0xA080) & 1, 0);
+        assert_eq!(receive(&mut cart), 0x1234);
+        deselect(&mut cart);
+
+        assert_eq!(cart.battery_ram().map(<[u8]>::len), Some(256));
+    }
+}
@@ -0,0 +1,185 @@
+//! <https://gbdev.io/pandocs/MMM01.html>
+use crate::cart::{banked_ram, banked_rom, store, Mapper, ROM_BANK_SIZE};
+use crate::header::{CartridgeHeader, CartridgeType};
+
+/// Header of the menu in the last 32 KiB, which is what the console boots.
+///
+/// The header at the start of the image belongs to the first game, so this is
+/// the only way to recognize an MMM01 dump.
+pub fn menu_header(rom: &[u8]) -> Option<CartridgeHeader> {
+    let menu = rom
+        .len()
+        .checked_sub(2 * ROM_BANK_SIZE)
+        .filter(|&menu| menu > 0)?;
+    let header = CartridgeHeader::parse(&rom[menu..]).ok()?;
+    let is_mmm01 = matches!(
+        header.cartridge_type,
+        CartridgeType::Mmm01 | CartridgeType::Mmm01Ram | CartridgeType::Mmm01RamBattery
+    );
+    (header.logo_valid && is_mmm01).then_some(header)
+}
+
+/// Multicart controller that boots into a menu in the last 32 KiB of ROM.
+///
+/// While unmapped the menu configures every register, including masks that
+/// lock bits of them. Setting the map enable bit then hands an MBC1 lookalike
+/// restricted to one game's banks over to that game.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default)]
+pub struct Mmm01 {
+    mapped: bool,
+    ram_enabled: bool,
+    /// 5 bit ROM bank low, 2 bit mid and 2 bit high make up a 9 bit bank
+    rom_low: u8,
+    rom_mid: u8,
+    rom_high: u8,
+    /// Bits of `rom_low` the game can't change, bits 1-4, set by bits 2-5 of 0x6000
+    rom_mask: u8,
+    ram_low: u8,
+    ram_high: u8,
+    /// Bits of `ram_low` the game can't change
+    ram_mask: u8,
+    mode: bool,
+    mode_locked: bool,
+}
+
+impl Mmm01 {
+    fn full_bank(&self, low: u8) -> usize {
+        usize::from(self.rom_high) << 7 | usize::from(self.rom_mid) << 5 | usize::from(low)
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.mode {
+            self.ram_low
+        } else {
+            self.ram_low & self.ram_mask
+        };
+        usize::from(self.ram_high) << 2 | usize::from(low)
+    }
+
+    /// Only the bits the mask leaves unlocked are writable once mapped
+    const fn masked(mapped: bool, old: u8, new: u8, mask: u8) -> u8 {
+        if mapped {
+            (old & mask) | (new & !mask)
+        } else {
+            new
+        }
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        if !self.mapped {
+            // The menu lives in the last 32 KiB
+            let menu = rom.len().saturating_sub(2 * ROM_BANK_SIZE) / ROM_BANK_SIZE;
+            return banked_rom(rom, menu + usize::from(address >= 0x4000), address);
+        }
+
+        // The locked bits are part of where the game starts
+        if address < 0x4000 {
+            return banked_rom(rom, self.full_bank(self.rom_low & self.rom_mask), address);
+        }
+
+        // 0 -> 1 only looks at the bits the game controls
+        let low = match self.rom_low & !self.rom_mask & 0x1F {
+            0 => self.rom_low | 1,
+            _ => self.rom_low,
+        };
+        banked_rom(rom, self.full_bank(low), address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        let mapped = self.mapped;
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !mapped {
+                    self.ram_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                self.rom_low = Self::masked(mapped, self.rom_low, value & 0x1F, self.rom_mask);
+                if !mapped {
+                    self.rom_mid = (value >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.ram_low = Self::masked(mapped, self.ram_low, value & 0x03, self.ram_mask);
+                if !mapped {
+                    self.ram_high = (value >> 2) & 0x03;
+                    self.rom_high = (value >> 4) & 0x03;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mode_locked {
+                    self.mode = value & 0x01 == 1;
+                }
+                if !mapped {
+                    self.rom_mask = (value >> 1) & 0x1E;
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+
+        banked_ram(ram, self.ram_bank(), address).map_or(0xFF, |i| ram[i])
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+
+        banked_ram(ram, self.ram_bank(), address).is_some_and(|i| store(ram, i, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 2 MiB with every 16 KiB bank numbered at 0x0000 and 0x0001
+    fn numbered_rom() -> Vec<u8> {
+        let mut rom = vec![0; 128 * ROM_BANK_SIZE];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+            chunk[1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    fn bank(mbc: &Mmm01, rom: &[u8], address: u16) -> u16 {
+        u16::from_le_bytes([mbc.read_rom(rom, address), mbc.read_rom(rom, address + 1)])
+    }
+
+    #[test]
+    fn test_menu_then_game() {
+        let rom = numbered_rom();
+        let mut mbc = Mmm01::default();
+        assert_eq!(bank(&mbc, &rom, 0x0000), 126);
+        assert_eq!(bank(&mbc, &rom, 0x4000), 127);
+
+        // Select the game starting at bank 0x20 (mid = 1), lock the top 2 bits of the ROM bank
+        mbc.write_rom(0x2000, 0x20);
+        mbc.write_rom(0x6000, 0x30);
+        mbc.write_rom(0x0000, 0x40);
+        assert_eq!(bank(&mbc, &rom, 0x0000), 0x20);
+        assert_eq!(bank(&mbc, &rom, 0x4000), 0x21);
+
+        // The game can only change the unlocked bits, and mid is fixed
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(bank(&mbc, &rom, 0x4000), 0x27);
+        mbc.write_rom(0x2000, 0x02);
+        assert_eq!(bank(&mbc, &rom, 0x4000), 0x22);
+
+        // Configuration registers are locked now
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(bank(&mbc, &rom, 0x0000), 0x20);
+    }
+}
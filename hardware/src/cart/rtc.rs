@@ -0,0 +1,269 @@
+//! Real-time clocks on the cartridge, driven by the host's time
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where cartridge clocks get the current time from, in whole seconds
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// Seconds since the unix epoch
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// A clock that only moves when told to, so tests can fast-forward time
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    seconds: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(seconds: u64) -> Arc<Self> {
+        Arc::new(Self {
+            seconds: AtomicU64::new(seconds),
+        })
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.seconds.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.seconds.load(Ordering::Relaxed)
+    }
+}
+
+/// Size of the clock footer BGB and VBA-M append to save files
+pub const RTC_FOOTER_SIZE: usize = 48;
+
+/// Encodes the clock footer: the live then the latched registers, each as a
+/// little endian u32, followed by the host time they were current at as a u64
+pub fn encode_footer(registers: [u8; 5], latched: [u8; 5], timestamp: u64) -> Vec<u8> {
+    let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+    for register in registers.into_iter().chain(latched) {
+        footer.extend(u32::from(register).to_le_bytes());
+    }
+    footer.extend(timestamp.to_le_bytes());
+    footer
+}
+
+/// Decodes a clock footer, also accepting the older 44 byte one with a u32 timestamp
+pub fn decode_footer(footer: &[u8]) -> Option<([u8; 5], [u8; 5], u64)> {
+    let timestamp = match footer.len() {
+        44 => u64::from(u32::from_le_bytes(footer[40..44].try_into().ok()?)),
+        RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().ok()?),
+        _ => return None,
+    };
+
+    let mut registers = [0; 10];
+    for (register, bytes) in registers.iter_mut().zip(footer.chunks_exact(4)) {
+        *register = bytes[0];
+    }
+    let (live, latched) = registers.split_at(5);
+    Some((live.try_into().ok()?, latched.try_into().ok()?, timestamp))
+}
+
+/// The MBC3 clock registers, selected with 0x08-0x0C in the RAM bank register
+///
+/// <https://gbdev.io/pandocs/MBC3.html#the-clock-counter-registers>
+#[derive(Debug, Clone)]
+pub struct Rtc {
+    clock: Arc<dyn Clock>,
+    /// Host time the registers were last brought up to date at
+    last_update: u64,
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    /// 9 bit day counter
+    pub days: u16,
+    pub halted: bool,
+    /// Set when the day counter overflows, until the game clears it
+    pub day_carry: bool,
+    latched: [u8; 5],
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl Rtc {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            last_update: clock.now(),
+            clock,
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.update();
+        self.last_update = clock.now();
+        self.clock = clock;
+    }
+
+    pub const fn last_update(&self) -> u64 {
+        self.last_update
+    }
+
+    /// Catches the registers up with the host clock
+    pub fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if !self.halted {
+            self.advance(elapsed);
+        }
+    }
+
+    /// Restores the registers as they were at host time `timestamp`, then catches up
+    pub fn restore(&mut self, registers: [u8; 5], latched: [u8; 5], timestamp: u64) {
+        self.write_registers(registers);
+        self.latched = latched;
+        self.last_update = timestamp;
+        self.update();
+    }
+
+    pub fn advance(&mut self, seconds: u64) {
+        // Registers can hold out of range values written by the game,
+        // so carry through each of them instead of working on a timestamp
+        let seconds = u64::from(self.seconds) + seconds;
+        self.seconds = (seconds % 60) as u8;
+
+        let minutes = u64::from(self.minutes) + seconds / 60;
+        self.minutes = (minutes % 60) as u8;
+
+        let hours = u64::from(self.hours) + minutes / 60;
+        self.hours = (hours % 24) as u8;
+
+        let days = u64::from(self.days) + hours / 24;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+
+    /// Copies the live registers into the ones the game reads
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = self.registers();
+    }
+
+    /// The live registers in the order they are selected, 0x08-0x0C
+    pub fn registers(&self) -> [u8; 5] {
+        let [day_low, day_high] = self.days.to_le_bytes();
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            day_low,
+            (day_high & 0x01) | u8::from(self.halted) << 6 | u8::from(self.day_carry) << 7,
+        ]
+    }
+
+    pub const fn latched(&self) -> [u8; 5] {
+        self.latched
+    }
+
+    const fn write_registers(&mut self, registers: [u8; 5]) {
+        let [seconds, minutes, hours, day_low, day_high] = registers;
+        self.seconds = seconds & 0x3F;
+        self.minutes = minutes & 0x3F;
+        self.hours = hours & 0x1F;
+        self.days = u16::from_le_bytes([day_low, day_high & 0x01]);
+        self.halted = day_high & 0x40 != 0;
+        self.day_carry = day_high & 0x80 != 0;
+    }
+
+    /// Reads the latched register selected by `select` (0x08-0x0C)
+    pub fn read(&self, select: u8) -> u8 {
+        let mask = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+        let i = usize::from(select - 0x08);
+        self.latched[i] & mask[i]
+    }
+
+    /// Writes the live register selected by `select` (0x08-0x0C)
+    pub fn write(&mut self, select: u8, value: u8) {
+        self.update();
+        let mut registers = self.registers();
+        registers[usize::from(select - 0x08)] = value;
+        self.write_registers(registers);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_advance() {
+        let clock = ManualClock::new(1000);
+        let mut rtc = Rtc::new(clock.clone());
+
+        clock.advance(59);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 59);
+
+        clock.advance(1 + 60 * 60 * 24 + 60 * 60 + 60);
+        rtc.latch();
+        assert_eq!(rtc.latched(), [0, 2, 1, 1, 0]);
+
+        // Day 511 rolls over into the carry flag
+        clock.advance(511 * 24 * 60 * 60);
+        rtc.latch();
+        assert_eq!(rtc.read(0x0B), 0);
+        assert_eq!(rtc.read(0x0C), 0x80);
+    }
+
+    #[test]
+    fn test_footer() {
+        let footer = encode_footer([1, 2, 3, 4, 0xC1], [5, 6, 7, 8, 0], 0x1_2345_6789);
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+        assert_eq!(&footer[16..20], &[0xC1, 0, 0, 0]);
+        assert_eq!(
+            decode_footer(&footer),
+            Some(([1, 2, 3, 4, 0xC1], [5, 6, 7, 8, 0], 0x1_2345_6789))
+        );
+
+        let short = &footer[..44];
+        assert_eq!(decode_footer(short).unwrap().2, 0x2345_6789);
+        assert_eq!(decode_footer(&footer[..40]), None);
+    }
+
+    #[test]
+    fn test_halt() {
+        let clock = ManualClock::new(0);
+        let mut rtc = Rtc::new(clock.clone());
+
+        rtc.write(0x0C, 0x40);
+        clock.advance(100);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 0);
+
+        rtc.write(0x0C, 0x00);
+        clock.advance(100);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 40);
+        assert_eq!(rtc.read(0x09), 1);
+    }
+}
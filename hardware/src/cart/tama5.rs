@@ -0,0 +1,130 @@
+//! Bandai TAMA5, found in Tamagotchi 3
+//!
+//! <https://gbdev.io/pandocs/TAMA5.html>
+use tracing::debug;
+
+use crate::cart::{banked_rom, store, Mapper};
+
+/// The TAMA6 microcontroller's 32 bytes of RAM
+pub const TAMA5_RAM_SIZE: usize = 0x20;
+
+/// Every access goes through a register select at 0xA001 and a nibble wide
+/// data port at 0xA000. RAM is read and written one byte at a time through
+/// commands, the RTC commands are not emulated.
+#[derive(Debug, Clone, Default)]
+pub struct Tama5 {
+    select: u8,
+    registers: [u8; 0x10],
+    /// Result of the last RAM read command
+    data_out: u8,
+}
+
+impl Tama5 {
+    fn rom_bank(&self) -> usize {
+        usize::from(self.registers[0] & 0x0F) | usize::from(self.registers[1] & 0x01) << 4
+    }
+
+    /// Runs the command in the registers, returning whether it changed RAM
+    fn command(&mut self, ram: &mut [u8]) -> bool {
+        let address = usize::from(self.registers[6] & 0x01) << 4 | usize::from(self.registers[7]);
+        match self.registers[6] >> 1 {
+            0x0 => return store(ram, address, self.registers[4] | self.registers[5] << 4),
+            0x1 => self.data_out = ram[address],
+            command => debug!("Unsupported TAMA5 command {command:#x}"),
+        }
+        false
+    }
+}
+
+impl Mapper for Tama5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => banked_rom(rom, 0, address),
+            _ => banked_rom(rom, self.rom_bank(), address),
+        }
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, _ram: &[u8], address: u16) -> u8 {
+        if address & 1 == 1 {
+            return 0xFF;
+        }
+
+        match self.select {
+            // Always ready for the next command
+            0x0A => 0xF1,
+            0x0C => 0xF0 | (self.data_out & 0x0F),
+            0x0D => 0xF0 | (self.data_out >> 4),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], address: u16, value: u8) -> bool {
+        if address & 1 == 1 {
+            self.select = value & 0x0F;
+            return false;
+        }
+
+        self.registers[usize::from(self.select)] = value & 0x0F;
+        // Writing the low address nibble runs the command
+        self.select == 0x07 && self.command(ram)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_register(mbc: &mut Tama5, ram: &mut [u8], register: u8, value: u8) {
+        mbc.write_ram(ram, 0xA001, register);
+        mbc.write_ram(ram, 0xA000, value);
+    }
+
+    #[test]
+    fn test_ram_and_banking() {
+        let mut mbc = Tama5::default();
+        let mut ram = vec![0; TAMA5_RAM_SIZE];
+
+        // Write 0x5A to address 0x13
+        for (register, value) in [(4, 0xA), (5, 0x5), (6, 0x1), (7, 0x3)] {
+            write_register(&mut mbc, &mut ram, register, value);
+        }
+        assert_eq!(ram[0x13], 0x5A);
+
+        // Read it back
+        for (register, value) in [(6, 0x3), (7, 0x3)] {
+            write_register(&mut mbc, &mut ram, register, value);
+        }
+        mbc.write_ram(&mut ram, 0xA001, 0x0C);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFA);
+        mbc.write_ram(&mut ram, 0xA001, 0x0D);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xF5);
+
+        let mut rom = vec![0; 0x8_0000];
+        rom[0x13 * 0x4000] = 0x13;
+        write_register(&mut mbc, &mut ram, 0, 0x3);
+        write_register(&mut mbc, &mut ram, 1, 0x1);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x13);
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        use crate::cart::Cartridge;
+        use crate::testing::make_rom;
+
+        let rom = make_rom("TAMA5", 0xFD, 0, 0);
+        let mut cart = Cartridge::new(rom.clone()).unwrap();
+        for (register, value) in [(4, 0xA), (5, 0x5), (6, 0x1), (7, 0x3)] {
+            cart.write(0xA001, register);
+            cart.write(0xA000, value);
+        }
+        let save = cart.save_data().unwrap();
+        assert_eq!(save.len(), TAMA5_RAM_SIZE);
+        assert_eq!(save[0x13], 0x5A);
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.load_save_data(&save);
+        assert_eq!(cart.ram()[0x13], 0x5A);
+    }
+}
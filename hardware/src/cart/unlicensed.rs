@@ -0,0 +1,124 @@
+//! Mappers of unlicensed carts, whose headers often don't say what they are
+//!
+//! Wisdom Tree's mapper is the only one emulated here. Other bootlegs are only
+//! told apart from their headers well enough to run on a stock MBC1 or MBC5,
+//! so clones that scramble or move the bank registers still misbank.
+use tracing::info;
+
+use crate::cart::{banked_rom, Mapper, MapperKind, ROM_BANK_SIZE};
+use crate::header::{CartridgeHeader, CartridgeType};
+
+/// MBC1 can't address more than 2 MiB, so bigger carts are assumed to be MBC5
+const MBC1_MAX_ROM: usize = 0x20_0000;
+
+/// Wisdom Tree's discrete logic mapper, switching all of 0x0000-0x7FFF in
+/// 32 KiB banks selected by the low byte of the address written to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WisdomTree {
+    bank: u8,
+}
+
+impl Mapper for WisdomTree {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = usize::from(self.bank) * 2 + usize::from(address >= 0x4000);
+        banked_rom(rom, bank, address)
+    }
+
+    fn write_rom(&mut self, address: u16, _value: u8) {
+        if address < 0x4000 {
+            self.bank = address as u8;
+        }
+    }
+
+    fn read_ram(&self, _ram: &[u8], _address: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_ram(&mut self, _ram: &mut [u8], _address: u16, _value: u8) -> bool {
+        false
+    }
+}
+
+/// Guesses the mapper of a cart whose header doesn't match its ROM, or
+/// `None` to go by the header.
+///
+/// Wisdom Tree carts are found by name. Otherwise a ROM only header too big
+/// for one bank, an MBC1 header too big for MBC1, or an unknown type gets
+/// whichever of MBC1 and MBC5 can address the whole ROM.
+pub fn detect(header: &CartridgeHeader, rom: &[u8]) -> Option<MapperKind> {
+    let kind = match header.cartridge_type {
+        CartridgeType::RomOnly if rom.len() > 2 * ROM_BANK_SIZE => {
+            if contains(rom, b"WISDOM TREE") || contains(rom, b"WISDOM\0TREE") {
+                MapperKind::WisdomTree
+            } else {
+                by_size(rom)
+            }
+        }
+        CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery
+            if rom.len() > MBC1_MAX_ROM =>
+        {
+            MapperKind::Mbc5
+        }
+        CartridgeType::Unknown(_) => by_size(rom),
+        _ => return None,
+    };
+
+    info!(
+        "{:?} header on a {} KiB ROM, guessing {kind}",
+        header.cartridge_type,
+        rom.len() / 1024
+    );
+    Some(kind)
+}
+
+const fn by_size(rom: &[u8]) -> MapperKind {
+    if rom.len() > MBC1_MAX_ROM {
+        MapperKind::Mbc5
+    } else {
+        MapperKind::Mbc1
+    }
+}
+
+fn contains(rom: &[u8], needle: &[u8]) -> bool {
+    rom.windows(needle.len()).any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::make_rom;
+
+    #[test]
+    fn test_wisdom_tree() {
+        let mut rom = make_rom("EXODUS", 0x00, 3, 0);
+        rom[0x150..0x15B].copy_from_slice(b"WISDOM TREE");
+        rom[3 * 0x8000 + 0x4000] = 0x33;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(detect(&header, &rom), Some(MapperKind::WisdomTree));
+
+        let mut mbc = WisdomTree::default();
+        mbc.write_rom(0x0003, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x33);
+        // The written value is ignored
+        mbc.write_rom(0x0000, 0x03);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x00);
+    }
+
+    #[test]
+    fn test_detect() {
+        let rom = make_rom("BOOTLEG", 0x00, 2, 0);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(detect(&header, &rom), Some(MapperKind::Mbc1));
+
+        let rom = make_rom("BOOTLEG", 0x01, 7, 0);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(detect(&header, &rom), Some(MapperKind::Mbc5));
+
+        let rom = make_rom("TETRIS", 0x00, 0, 0);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(detect(&header, &rom), None);
+        let rom = make_rom("ZELDA", 0x03, 4, 3);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(detect(&header, &rom), None);
+    }
+}
@@ -0,0 +1,320 @@
+//! Game Genie and GameShark cheat codes
+//!
+//! Game Genie codes patch ROM as the CPU reads it, GameShark codes write
+//! RAM once per frame.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatCode {
+    /// `ABC-DEF` or `ABC-DEF-GHI`, replacing the ROM byte at `address` with
+    /// `value`, but only while it reads `compare` when given
+    GameGenie {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// `TTVVLLHH`, writing `value` to `address` every frame. `bank` is the
+    /// cartridge RAM bank for 0xA000-0xBFFF, or `None` for whatever is mapped.
+    GameShark {
+        bank: Option<u8>,
+        address: u16,
+        value: u8,
+    },
+}
+
+impl FromStr for CheatCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.contains('-') {
+            parse_game_genie(s)
+        } else {
+            parse_game_shark(s)
+        }
+        .with_context(|| format!("Invalid cheat code {s:?}"))
+    }
+}
+
+fn hex_digits(s: &str) -> crate::Result<Vec<u8>> {
+    s.chars()
+        .filter(|c| *c != '-')
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .with_context(|| format!("{c:?} is not a hex digit"))
+        })
+        .collect()
+}
+
+/// <https://gbdev.gg8.se/wiki/articles/Game_Genie>
+fn parse_game_genie(s: &str) -> crate::Result<CheatCode> {
+    let groups: Vec<_> = s.split('-').collect();
+    if !matches!(groups.len(), 2 | 3) || groups.iter().any(|group| group.len() != 3) {
+        bail!("Game Genie codes look like ABC-DEF or ABC-DEF-GHI");
+    }
+
+    let d = hex_digits(s)?;
+    let value = d[0] << 4 | d[1];
+    let address = u16::from(d[5] ^ 0x0F) << 12
+        | u16::from(d[2]) << 8
+        | u16::from(d[3]) << 4
+        | u16::from(d[4]);
+    // H is only there to make the code harder to guess
+    let compare = (d.len() == 9).then(|| (d[6] << 4 | d[8]).rotate_right(2) ^ 0xBA);
+
+    if address >= 0x8000 {
+        bail!("Game Genie codes can only patch ROM, not {address:#06x}");
+    }
+
+    Ok(CheatCode::GameGenie {
+        address,
+        value,
+        compare,
+    })
+}
+
+fn parse_game_shark(s: &str) -> crate::Result<CheatCode> {
+    if s.len() != 8 {
+        bail!("GameShark codes are 8 hex digits");
+    }
+
+    let d = hex_digits(s)?;
+    let byte = |i: usize| d[i] << 4 | d[i + 1];
+    let address = u16::from_le_bytes([byte(4), byte(6)]);
+    let bank = match byte(0) {
+        // 0x90-0x97 select work RAM banks, which the DMG doesn't have
+        0x01 | 0x90..=0x97 => None,
+        kind @ 0x80..=0x8F if matches!(address, 0xA000..=0xBFFF) => Some(kind & 0x0F),
+        kind => bail!("Unsupported GameShark code type {kind:#04x}"),
+    };
+
+    Ok(CheatCode::GameShark {
+        bank,
+        address,
+        value: byte(2),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub code: CheatCode,
+    /// The code as it was entered
+    pub text: String,
+    pub enabled: bool,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(f, "{} {} ({state})", self.text, self.name)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    /// `game.gb` keeps its cheats in `game.cht`
+    pub fn path_for_rom(rom: impl AsRef<Path>) -> PathBuf {
+        rom.as_ref().with_extension("cht")
+    }
+
+    /// Adds an enabled cheat, returning its index
+    pub fn add(&mut self, name: &str, code: &str) -> crate::Result<usize> {
+        self.cheats.push(Cheat {
+            name: name.to_owned(),
+            code: code.parse()?,
+            text: code.trim().to_owned(),
+            enabled: true,
+        });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> crate::Result<()> {
+        let Some(cheat) = self.cheats.get_mut(index) else {
+            bail!("No cheat #{index}");
+        };
+        cheat.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn enable(&mut self, index: usize) -> crate::Result<()> {
+        self.set_enabled(index, true)
+    }
+
+    pub fn disable(&mut self, index: usize) -> crate::Result<()> {
+        self.set_enabled(index, false)
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Adds every cheat in a cheat file: one code per line followed by its
+    /// name, `#` starting a comment and `!` marking a disabled code
+    pub fn load(&mut self, text: &str) -> crate::Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (enabled, line) = line
+                .strip_prefix('!')
+                .map_or((true, line), |line| (false, line.trim_start()));
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let index = self
+                .add(name.trim(), code)
+                .with_context(|| format!("Line {}", number + 1))?;
+            self.set_enabled(index, enabled)?;
+        }
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> crate::Result<()> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        self.load(&text)?;
+        info!(
+            "Loaded {} cheats from {}",
+            self.cheats.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Applies Game Genie codes to `value`, read from ROM at `address`
+    pub fn patch_read(&self, address: u16, value: u8) -> u8 {
+        self.enabled()
+            .find_map(|code| match *code {
+                CheatCode::GameGenie {
+                    address: target,
+                    value: patched,
+                    compare,
+                } if target == address && compare.is_none_or(|c| c == value) => Some(patched),
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+
+    /// The GameShark writes to apply at the end of a frame
+    pub fn frame_writes(&self) -> impl Iterator<Item = (Option<u8>, u16, u8)> + '_ {
+        self.enabled().filter_map(|code| match *code {
+            CheatCode::GameShark {
+                bank,
+                address,
+                value,
+            } => Some((bank, address, value)),
+            CheatCode::GameGenie { .. } => None,
+        })
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &CheatCode> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| &cheat.code)
+    }
+}
+
+/// Loads `game.cht` next to `game.gb`, if there is one
+pub fn load_for_rom(rom: impl AsRef<Path>) -> Cheats {
+    let mut cheats = Cheats::default();
+    let path = Cheats::path_for_rom(rom);
+    if path.is_file() {
+        if let Err(e) = cheats.load_file(&path) {
+            warn!("Ignoring cheats: {e:#}");
+            return Cheats::default();
+        }
+    }
+    cheats
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_game_genie() {
+        // Infinite lives in Super Mario Land
+        assert_eq!(
+            "00A-17B-C49".parse::<CheatCode>().unwrap(),
+            CheatCode::GameGenie {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }
+        );
+        assert_eq!(
+            "3E1-DAF".parse::<CheatCode>().unwrap(),
+            CheatCode::GameGenie {
+                address: 0x01DA,
+                value: 0x3E,
+                compare: None,
+            }
+        );
+        assert!("00A-17B-C4".parse::<CheatCode>().is_err());
+        assert!("00A-170-C49".parse::<CheatCode>().is_err());
+    }
+
+    #[test]
+    fn test_game_shark() {
+        assert_eq!(
+            "0105ADD2".parse::<CheatCode>().unwrap(),
+            CheatCode::GameShark {
+                bank: None,
+                address: 0xD2AD,
+                value: 0x05,
+            }
+        );
+        assert_eq!(
+            "8299 00A0".replace(' ', "").parse::<CheatCode>().unwrap(),
+            CheatCode::GameShark {
+                bank: Some(2),
+                address: 0xA000,
+                value: 0x99,
+            }
+        );
+        assert!("0F05ADD2".parse::<CheatCode>().is_err());
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut cheats = Cheats::default();
+        cheats
+            .load("00A-17B-C49 Infinite lives\n# comment\n! 0105ADD2 Ammo\n\n")
+            .unwrap();
+        assert_eq!(cheats.list().len(), 2);
+        assert_eq!(cheats.list()[0].name, "Infinite lives");
+        assert_eq!(cheats.list()[1].to_string(), "0105ADD2 Ammo (off)");
+
+        assert_eq!(cheats.patch_read(0x4A17, 0xC8), 0x00);
+        assert_eq!(cheats.patch_read(0x4A17, 0x12), 0x12);
+        assert_eq!(cheats.frame_writes().count(), 0);
+
+        cheats.enable(1).unwrap();
+        cheats.disable(0).unwrap();
+        assert_eq!(cheats.patch_read(0x4A17, 0xC8), 0xC8);
+        assert_eq!(
+            cheats.frame_writes().collect::<Vec<_>>(),
+            [(None, 0xD2AD, 0x05)]
+        );
+        assert!(cheats.enable(2).is_err());
+        assert!(cheats.load("XYZ").is_err());
+    }
+}
@@ -1,9 +1,9 @@
 use crate::{
     instructions::{Instruction, INSTRUCTIONS},
     mem::MMU,
+    ppu::PpuBackend,
     reg::FlagBit,
     reg::Registers,
-    GPU,
 };
 use tracing::{debug, info, trace};
 
@@ -11,7 +11,6 @@ use tracing::{debug, info, trace};
 pub struct CPU {
     pub reg: Registers,
     pub mmu: MMU,
-    pub gpu: GPU,
 }
 
 impl CPU {
@@ -19,7 +18,13 @@ impl CPU {
         Self {
             reg: Registers::new(),
             mmu: MMU::new(),
-            gpu: GPU::new(),
+        }
+    }
+
+    pub fn with_ppu_backend(backend: PpuBackend) -> Self {
+        Self {
+            reg: Registers::new(),
+            mmu: MMU::with_ppu_backend(backend),
         }
     }
 }
@@ -103,8 +108,10 @@ impl CPU {
         debug!(instruction_bytes);
     }
 
-    pub fn cycle(&mut self) {
+    /// Runs one instruction, returning how many machine cycles it took
+    pub fn cycle(&mut self) -> u16 {
         trace!("cycle====================================");
+        self.mmu.set_pc(self.reg.pc);
         let instruction = self.fetch();
 
         debug!(
@@ -113,8 +120,10 @@ impl CPU {
         );
 
         self.reg.pc += instruction.run(self);
+        self.mmu.tick(instruction.cycles);
 
         self.print_reg();
+        instruction.cycles
     }
 
     pub fn reset(&mut self) {
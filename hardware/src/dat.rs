@@ -0,0 +1,303 @@
+//! Identifying ROMs against a Logiqx XML DAT file, like the ones No-Intro publishes
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use tracing::debug;
+
+/// The checksums DAT files list for each ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomHashes {
+    pub size: usize,
+    pub crc32: u32,
+    /// Lowercase hex
+    pub md5: String,
+    /// Lowercase hex
+    pub sha1: String,
+}
+
+impl RomHashes {
+    pub fn compute(rom: &[u8]) -> Self {
+        Self {
+            size: rom.len(),
+            crc32: crc32fast::hash(rom),
+            md5: format!("{:x}", Md5::digest(rom)),
+            sha1: format!("{:x}", Sha1::digest(rom)),
+        }
+    }
+}
+
+impl fmt::Display for RomHashes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CRC32 {:08x}, MD5 {}, SHA-1 {}",
+            self.crc32, self.md5, self.sha1
+        )
+    }
+}
+
+/// What the DAT says about a dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpStatus {
+    /// Confirmed by several dumps
+    Verified,
+    /// Believed good, but not verified
+    Good,
+    /// Known to be a bad dump
+    Bad,
+    /// A good dump with extra data after it, usually padding to a bigger size
+    Overdump,
+}
+
+impl fmt::Display for DumpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Verified => "verified",
+            Self::Good => "good",
+            Self::Bad => "bad dump",
+            Self::Overdump => "overdump",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatEntry {
+    /// Canonical name, like "Tetris (World) (Rev 1)"
+    pub name: String,
+    pub region: Option<String>,
+    pub revision: Option<String>,
+    pub status: DumpStatus,
+    pub size: usize,
+    pub crc32: u32,
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+}
+
+impl DatEntry {
+    fn matches(&self, hashes: &RomHashes) -> bool {
+        if let Some(sha1) = &self.sha1 {
+            return *sha1 == hashes.sha1;
+        }
+        if let Some(md5) = &self.md5 {
+            return *md5 == hashes.md5;
+        }
+        self.size == hashes.size && self.crc32 == hashes.crc32
+    }
+}
+
+/// A ROM found in a DAT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identification {
+    pub entry: DatEntry,
+    /// The entry's status, or [`DumpStatus::Overdump`] if only a prefix matched
+    pub status: DumpStatus,
+    pub hashes: RomHashes,
+}
+
+impl Identification {
+    pub fn name(&self) -> &str {
+        &self.entry.name
+    }
+}
+
+impl fmt::Display for Identification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.entry.name, self.status)?;
+        if let Some(region) = &self.entry.region {
+            write!(f, ", region {region}")?;
+        }
+        if let Some(revision) = &self.entry.revision {
+            write!(f, ", {revision}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Dat {
+    /// Name from the DAT's header, like "Nintendo - Game Boy"
+    pub name: String,
+    pub entries: Vec<DatEntry>,
+}
+
+impl Dat {
+    pub fn load(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let xml = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&xml).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(xml: &str) -> crate::Result<Self> {
+        let doc = roxmltree::Document::parse(xml)?;
+        let root = doc.root_element();
+        if !root.has_tag_name("datafile") {
+            bail!("Expected a <datafile>, found <{}>", root.tag_name().name());
+        }
+
+        let name = root
+            .children()
+            .find(|node| node.has_tag_name("header"))
+            .and_then(|header| header.children().find(|node| node.has_tag_name("name")))
+            .and_then(|name| name.text())
+            .unwrap_or_default()
+            .to_owned();
+
+        let mut entries = Vec::new();
+        for game in root.children().filter(|node| {
+            // MAME derived DATs call them machines
+            node.has_tag_name("game") || node.has_tag_name("machine")
+        }) {
+            let Some(game_name) = game.attribute("name") else {
+                continue;
+            };
+            let (region, revision) = parse_name(game_name);
+
+            for rom in game.children().filter(|node| node.has_tag_name("rom")) {
+                let crc32 = rom
+                    .attribute("crc")
+                    .map(|crc| u32::from_str_radix(crc, 16))
+                    .transpose()
+                    .with_context(|| format!("Bad CRC in {game_name}"))?;
+                let Some(crc32) = crc32 else {
+                    debug!("Skipping {game_name} without a CRC");
+                    continue;
+                };
+
+                entries.push(DatEntry {
+                    name: game_name.to_owned(),
+                    region: region.clone(),
+                    revision: revision.clone(),
+                    status: match rom.attribute("status") {
+                        Some("verified") => DumpStatus::Verified,
+                        Some("baddump" | "nodump") => DumpStatus::Bad,
+                        _ => DumpStatus::Good,
+                    },
+                    size: rom
+                        .attribute("size")
+                        .and_then(|size| size.parse().ok())
+                        .unwrap_or_default(),
+                    crc32,
+                    md5: rom.attribute("md5").map(str::to_ascii_lowercase),
+                    sha1: rom.attribute("sha1").map(str::to_ascii_lowercase),
+                });
+            }
+        }
+
+        debug!("Loaded {} entries from DAT {name:?}", entries.len());
+        Ok(Self { name, entries })
+    }
+
+    pub fn identify(&self, rom: &[u8]) -> Option<Identification> {
+        let hashes = RomHashes::compute(rom);
+        if let Some(entry) = self.entries.iter().find(|entry| entry.matches(&hashes)) {
+            return Some(Identification {
+                entry: entry.clone(),
+                status: entry.status,
+                hashes,
+            });
+        }
+
+        // An overdump starts with a good dump, so try every smaller size in the DAT
+        let sizes: BTreeSet<_> = self
+            .entries
+            .iter()
+            .map(|entry| entry.size)
+            .filter(|size| (1..rom.len()).contains(size))
+            .collect();
+        sizes.into_iter().find_map(|size| {
+            let prefix = RomHashes::compute(&rom[..size]);
+            self.entries
+                .iter()
+                .find(|entry| entry.matches(&prefix))
+                .map(|entry| Identification {
+                    entry: entry.clone(),
+                    status: DumpStatus::Overdump,
+                    hashes: hashes.clone(),
+                })
+        })
+    }
+}
+
+/// Pulls the region and revision out of a No-Intro name, where the region is
+/// the first parenthesized tag and the revision looks like "(Rev 1)" or "(Rev A)"
+fn parse_name(name: &str) -> (Option<String>, Option<String>) {
+    let tags: Vec<_> = name
+        .split('(')
+        .skip(1)
+        .filter_map(|tag| tag.split_once(')').map(|(tag, _)| tag.trim()))
+        .collect();
+
+    let region = tags.first().map(|tag| (*tag).to_owned());
+    let revision = tags
+        .iter()
+        .find(|tag| tag.starts_with("Rev ") || *tag == &"Rev")
+        .map(|tag| (*tag).to_owned());
+    (region, revision)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dat_for(rom: &[u8], status: &str) -> String {
+        let hashes = RomHashes::compute(rom);
+        format!(
+            r#"<?xml version="1.0"?>
+<datafile>
+    <header><name>Nintendo - Game Boy</name></header>
+    <game name="Other Game (Japan)">
+        <rom name="Other Game (Japan).gb" size="4" crc="12345678"/>
+    </game>
+    <game name="Test Game (USA, Europe) (Rev 1)">
+        <description>Test Game (USA, Europe) (Rev 1)</description>
+        <rom name="Test Game (USA, Europe) (Rev 1).gb" size="{}" crc="{:08X}" md5="{}" sha1="{}" {status}/>
+    </game>
+</datafile>"#,
+            hashes.size, hashes.crc32, hashes.md5, hashes.sha1
+        )
+    }
+
+    #[test]
+    fn test_identify() {
+        let rom: Vec<u8> = (0..=255).collect();
+        let dat = Dat::parse(&dat_for(&rom, r#"status="verified""#)).unwrap();
+        assert_eq!(dat.name, "Nintendo - Game Boy");
+        assert_eq!(dat.entries.len(), 2);
+
+        let id = dat.identify(&rom).unwrap();
+        assert_eq!(id.name(), "Test Game (USA, Europe) (Rev 1)");
+        assert_eq!(id.status, DumpStatus::Verified);
+        assert_eq!(id.entry.region.as_deref(), Some("USA, Europe"));
+        assert_eq!(id.entry.revision.as_deref(), Some("Rev 1"));
+        assert_eq!(
+            id.to_string(),
+            "Test Game (USA, Europe) (Rev 1) (verified, region USA, Europe, Rev 1)"
+        );
+
+        let mut overdump = rom.clone();
+        overdump.extend([0xFF; 256]);
+        assert_eq!(
+            dat.identify(&overdump).unwrap().status,
+            DumpStatus::Overdump
+        );
+
+        assert_eq!(dat.identify(&rom[1..]), None);
+
+        let dat = Dat::parse(&dat_for(&rom, r#"status="baddump""#)).unwrap();
+        assert_eq!(dat.identify(&rom).unwrap().status, DumpStatus::Bad);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let hashes = RomHashes::compute(b"abc");
+        assert_eq!(hashes.crc32, 0x3524_41C2);
+        assert_eq!(hashes.md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(hashes.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}
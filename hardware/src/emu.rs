@@ -1,53 +1,280 @@
 use std::{
+    path::{Path, PathBuf},
     process::exit,
     sync::{
-        mpsc::{self, Receiver},
-        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, PoisonError, RwLock,
     },
-    thread::{self, spawn},
-    time::Duration,
+    thread::{self, spawn, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use tracing::{error, trace};
 
-use crate::{cpu::CPU, mem::load_rom};
+use crate::{
+    cart::LoadOptions, cheats, cpu::CPU, mem::load_rom, palette::PaletteChoice, ppu::PpuBackend,
+    save::SaveFile, screenshot,
+};
+
+/// `LD B, B`, which debuggers treat as a breakpoint
+const SOFTWARE_BREAKPOINT: u8 = 0x40;
+
+/// How to run the emulator, as opposed to how to load the ROM
+#[derive(Debug, Clone, Default)]
+pub struct EmuOptions {
+    pub ppu: PpuBackend,
+    /// Log VRAM and OAM accesses that real hardware would ignore
+    pub warn_blocked_access: bool,
+    pub palette: PaletteChoice,
+    /// Stop after this many frames, instead of running forever. These runs
+    /// aren't paced to real time, they go as fast as the host allows.
+    pub frames: Option<u64>,
+    pub screenshot: ScreenshotOptions,
+    /// Ends the run cleanly once set, e.g. from a Ctrl-C handler
+    pub stop: Arc<AtomicBool>,
+}
+
+/// When to save the screen to a PNG
+#[derive(Debug, Clone, Default)]
+pub struct ScreenshotOptions {
+    /// Where the screen goes when the run ends, however it ends. Captures
+    /// during the run go next to it, numbered by frame, and breakpoint
+    /// captures also by how many breakpoints were hit before.
+    pub path: Option<PathBuf>,
+    /// Capture once this many frames have been drawn
+    pub frame: Option<u64>,
+    /// Capture whenever the ROM reaches a software breakpoint
+    pub on_breakpoint: bool,
+    /// How many times larger than the LCD, where 0 is treated as 1
+    pub scale: u32,
+}
 
-pub fn run_emulation(rom: &str) -> crate::Result<()> {
-    let cpu = CPU::new();
+pub fn run_emulation(rom: &str, options: &LoadOptions, emu: &EmuOptions) -> crate::Result<()> {
+    let mut cpu = CPU::with_ppu_backend(emu.ppu);
+    cpu.mmu.set_warn_blocked_access(emu.warn_blocked_access);
 
-    if let Err(e) = load_rom(rom, &cpu.mmu) {
+    if let Err(e) = load_rom(rom, options, &cpu.mmu) {
         error!("Failed to load ROM: {}", e);
         exit(1);
     }
 
+    *cpu.mmu.cheats_mut() = cheats::load_for_rom(rom);
+
+    let palette = emu
+        .palette
+        .resolve(&cpu.mmu.cartridge().read().unwrap().header);
+    cpu.mmu.ppu_mut().set_palette(palette);
+
+    // Flushed periodically below, and once more when this returns
+    let cart = Arc::clone(cpu.mmu.cartridge());
+    let identity = cart.read().unwrap().identity.clone();
+    let mut save = match identity {
+        Some(identity) => SaveFile::for_game(rom, identity.name(), cart),
+        None => SaveFile::for_rom(rom, cart),
+    };
+    if let Err(e) = save.load() {
+        error!("Failed to load {}: {}", save.path().display(), e);
+    }
+
     // box that bitch up for sharing
     let p_cpu = Arc::new(RwLock::new(cpu));
-    let r_cpu = setup_thread(Arc::clone(&p_cpu));
+    let _final_screenshot = FinalScreenshot {
+        cpu: Arc::clone(&p_cpu),
+        options: emu.screenshot.clone(),
+    };
+    // Declared last so it's joined before the final screenshot and save
+    let cpu_thread = CpuThread::spawn(
+        Arc::clone(&p_cpu),
+        emu.frames.is_none(),
+        emu.screenshot.on_breakpoint,
+    );
+    let mut frame = 0;
+    let mut breakpoints = 0;
 
     loop {
-        match r_cpu.recv() {
-            Ok(_cpu_state) => {}
+        let Some(step) = cpu_thread.step() else {
+            error!("cpu thread died");
+            error!("goodbye :(");
+            anyhow::bail!("");
+        };
+        if emu.stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        if let Err(e) = save.poll() {
+            error!("Failed to write {}: {}", save.path().display(), e);
+        }
+
+        let cpu = p_cpu.read().unwrap_or_else(PoisonError::into_inner);
+        match step {
+            Step::Frame => {
+                frame += 1;
+                if emu.screenshot.frame == Some(frame) {
+                    take_screenshot(&cpu, &emu.screenshot, Some(&frame.to_string()));
+                }
+                if emu.frames.is_some_and(|frames| frame >= frames) {
+                    return Ok(());
+                }
+            }
+            Step::LcdOff => {}
+            Step::Breakpoint => {
+                breakpoints += 1;
+                let suffix = format!("{frame}-{breakpoints}");
+                take_screenshot(&cpu, &emu.screenshot, Some(&suffix));
+            }
+        }
+    }
+}
+
+/// Why the CPU thread stopped to report back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// The PPU finished a frame
+    Frame,
+    /// A frame's worth of cycles ran with the LCD off
+    LcdOff,
+    /// The next instruction is a software breakpoint
+    Breakpoint,
+}
+
+/// Runs the CPU a frame at a time in lockstep with the main loop, which has
+/// the CPU to itself between steps
+struct CpuThread {
+    steps: Receiver<Step>,
+    resume: Sender<()>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CpuThread {
+    /// With `throttle`, steps are paced to [`crate::FPS`] frames a second.
+    /// With `breakpoints`, steps also end before software breakpoints.
+    fn spawn(cpu: Arc<RwLock<CPU>>, throttle: bool, breakpoints: bool) -> Self {
+        let (step_sender, steps) = mpsc::channel();
+        let (resume, resumed) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
 
-            Err(e) => {
-                error!("cpu thread died \n{e}");
-                error!("goodbye :(");
-                anyhow::bail!("");
+        let handle = spawn(move || {
+            let frame_time = Duration::from_secs(1) / crate::FPS as u32;
+            let mut next_frame = Instant::now();
+            // Either channel closing means the main loop is gone
+            while resumed.recv().is_ok() && !thread_stop.load(Ordering::Relaxed) {
+                let Ok(step) = cpu.write().map(|mut cpu| run_step(&mut cpu, breakpoints)) else {
+                    break;
+                };
+                if step_sender.send(step).is_err() {
+                    break;
+                }
+                trace!("step finished: {step:?}");
+
+                if throttle && step != Step::Breakpoint {
+                    next_frame += frame_time;
+                    match next_frame.checked_duration_since(Instant::now()) {
+                        Some(wait) => thread::sleep(wait),
+                        // Running behind, so don't try to catch up
+                        None => next_frame = Instant::now(),
+                    }
+                }
             }
+        });
+
+        Self {
+            steps,
+            resume,
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Lets the thread run one step and waits for it, or `None` if the thread died
+    fn step(&self) -> Option<Step> {
+        self.resume.send(()).ok()?;
+        self.steps.recv().ok()
+    }
+}
+
+impl Drop for CpuThread {
+    /// Stops the thread once its current step is done, and waits for it to exit
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wakes the thread if it's waiting to be resumed
+        let _ = self.resume.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Runs the CPU until the PPU finishes a frame, or for as long as a frame
+/// takes while the LCD is off
+fn run_step(cpu: &mut CPU, breakpoints: bool) -> Step {
+    let mut m_cycles = 0;
+    loop {
+        m_cycles += u32::from(cpu.cycle());
+        if cpu.mmu.ppu_mut().take_frame_ready() {
+            return Step::Frame;
+        }
+        if m_cycles >= crate::M_CYCLES_PER_FRAME && !cpu.mmu.ppu().regs.lcd_enabled() {
+            return Step::LcdOff;
+        }
+        if breakpoints && cpu.mmu.read(cpu.reg.pc) == SOFTWARE_BREAKPOINT {
+            return Step::Breakpoint;
         }
     }
 }
 
-fn setup_thread(cpu: Arc<RwLock<CPU>>) -> Receiver<Arc<RwLock<CPU>>> {
-    let (cpu_sender, cpu_receiver) = mpsc::channel();
+/// Captures the screen when the run ends, including when the CPU thread dies,
+/// since whatever was on screen is often the best clue to why
+struct FinalScreenshot {
+    cpu: Arc<RwLock<CPU>>,
+    options: ScreenshotOptions,
+}
+
+impl Drop for FinalScreenshot {
+    fn drop(&mut self) {
+        let cpu = self.cpu.read().unwrap_or_else(PoisonError::into_inner);
+        take_screenshot(&cpu, &self.options, None);
+    }
+}
 
-    let _cpu_thread = spawn(move || loop {
-        let _ = cpu.write().map(|mut c| c.cycle());
+/// Saves the screen to the path in `options`, with `suffix` added to its name
+fn take_screenshot(cpu: &CPU, options: &ScreenshotOptions, suffix: Option<&str>) {
+    let Some(path) = &options.path else {
+        return;
+    };
+    let path = suffix.map_or_else(|| path.clone(), |suffix| numbered_path(path, suffix));
+    if let Err(e) = screenshot::save_png(cpu.mmu.ppu(), &path, options.scale.max(1)) {
+        error!("Failed to save screenshot: {e:#}");
+    }
+}
 
-        cpu_sender.send(Arc::clone(&cpu)).unwrap();
-        trace!("cycle finished");
+/// `shot.png` becomes `shot-120.png` for frame 120, or `shot-120-2.png` for
+/// the second breakpoint, hit in frame 120
+fn numbered_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}-{suffix}");
+    if let Some(extension) = path.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
 
-        thread::sleep(Duration::from_millis(1000 / crate::FPS as u64));
-    });
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    cpu_receiver
+    #[test]
+    fn test_numbered_path() {
+        assert_eq!(
+            numbered_path(Path::new("out/shot.png"), "120"),
+            Path::new("out/shot-120.png")
+        );
+        assert_eq!(
+            numbered_path(Path::new("out/shot.png"), "120-2"),
+            Path::new("out/shot-120-2.png")
+        );
+        assert_eq!(numbered_path(Path::new("shot"), "3"), Path::new("shot-3"));
+    }
 }
@@ -0,0 +1,755 @@
+//! Cartridge header parsing
+//!
+//! <https://gbdev.io/pandocs/The_Cartridge_Header.html>
+use std::fmt;
+
+use anyhow::bail;
+
+use crate::NINTENDO_HEADER;
+
+pub const HEADER_END: usize = 0x0150;
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub entry_point: [u8; 4],
+    /// Whether the logo at 0x0104-0x0133 matches the one the boot ROM checks
+    pub logo_valid: bool,
+    /// Up to 16 bytes on DMG carts and 15 on CGB ones, where the last 4 may
+    /// be a manufacturer code instead
+    pub title: String,
+    /// 0x0134-0x0143 as is, including any manufacturer code and CGB flag
+    pub raw_title: [u8; 16],
+    /// Four character code found on some later titles, at the end of the
+    /// title area. Only a guess, since ordinary titles can look the same.
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub sgb_flag: bool,
+    pub cartridge_type: CartridgeType,
+    /// ROM size in bytes
+    pub rom_size: usize,
+    /// External RAM size in bytes
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(bytes: &[u8]) -> crate::Result<Self> {
+        if bytes.len() < HEADER_END {
+            bail!(
+                "ROM is too small to contain a header ({} bytes)",
+                bytes.len()
+            );
+        }
+
+        let mut entry_point = [0; 4];
+        entry_point.copy_from_slice(&bytes[0x0100..0x0104]);
+
+        let cgb_flag = CgbFlag::from(bytes[0x0143]);
+        let old_licensee = bytes[0x014B];
+        // Manufacturer codes came along with the new licensee codes
+        let manufacturer_code = match cgb_flag {
+            CgbFlag::Dmg => None,
+            _ if old_licensee != 0x33 => None,
+            _ => decode_manufacturer_code(&bytes[0x013F..0x0143]),
+        };
+
+        // The title shrank from 16 to 15 bytes once the CGB flag was introduced.
+        // It's kept whole, since a manufacturer code can't be told from the end
+        // of a long title.
+        let title_end = match cgb_flag {
+            CgbFlag::Dmg => 0x0144,
+            _ => 0x0143,
+        };
+        let title = decode_title(&bytes[0x0134..title_end]);
+        let mut raw_title = [0; 16];
+        raw_title.copy_from_slice(&bytes[0x0134..0x0144]);
+
+        let licensee = if old_licensee == 0x33 {
+            Licensee::New([bytes[0x0144], bytes[0x0145]])
+        } else {
+            Licensee::Old(old_licensee)
+        };
+
+        let header_checksum = bytes[0x014D];
+        let global_checksum = u16::from_be_bytes([bytes[0x014E], bytes[0x014F]]);
+
+        Ok(Self {
+            entry_point,
+            logo_valid: bytes[0x0104..0x0134] == NINTENDO_HEADER,
+            title,
+            raw_title,
+            manufacturer_code,
+            cgb_flag,
+            // The SGB flag is ignored by the SGB BIOS unless the old licensee is 0x33
+            sgb_flag: bytes[0x0146] == 0x03 && old_licensee == 0x33,
+            cartridge_type: CartridgeType::from(bytes[0x0147]),
+            rom_size: rom_size(bytes[0x0148]),
+            ram_size: ram_size(bytes[0x0149]),
+            destination: Destination::from(bytes[0x014A]),
+            licensee,
+            version: bytes[0x014C],
+            header_checksum,
+            global_checksum,
+            header_checksum_valid: compute_header_checksum(bytes) == header_checksum,
+            global_checksum_valid: compute_global_checksum(bytes) == global_checksum,
+        })
+    }
+}
+
+impl CartridgeHeader {
+    /// The sum of the raw title bytes, which the CGB boot ROM uses to colorize DMG games
+    pub fn title_checksum(&self) -> u8 {
+        self.raw_title
+            .iter()
+            .fold(0, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    /// Checks the header against itself and an image of `rom_len` bytes
+    pub fn validate(&self, rom_len: usize) -> ValidationReport {
+        let mut problems = Vec::new();
+        if !self.logo_valid {
+            problems.push(HeaderProblem::InvalidLogo);
+        }
+        if !self.header_checksum_valid {
+            problems.push(HeaderProblem::HeaderChecksum(self.header_checksum));
+        }
+        if !self.global_checksum_valid {
+            problems.push(HeaderProblem::GlobalChecksum(self.global_checksum));
+        }
+        if self.rom_size != rom_len {
+            problems.push(HeaderProblem::SizeMismatch {
+                declared: self.rom_size,
+                actual: rom_len,
+            });
+        }
+        if let CartridgeType::Unknown(code) = self.cartridge_type {
+            problems.push(HeaderProblem::UnknownCartridgeType(code));
+        }
+
+        ValidationReport { problems }
+    }
+}
+
+/// Everything wrong with a header. None of it stops a ROM from running in an
+/// emulator, but the boot ROM locks up on a bad logo or header checksum.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub problems: Vec<HeaderProblem>,
+}
+
+impl ValidationReport {
+    pub const fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{problem}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderProblem {
+    InvalidLogo,
+    /// The stored checksum, which doesn't match the header
+    HeaderChecksum(u8),
+    /// The stored checksum, which doesn't match the ROM
+    GlobalChecksum(u16),
+    SizeMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    UnknownCartridgeType(u8),
+}
+
+impl fmt::Display for HeaderProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLogo => write!(f, "logo doesn't match the boot ROM's"),
+            Self::HeaderChecksum(stored) => write!(f, "header checksum {stored:#04x} mismatch"),
+            Self::GlobalChecksum(stored) => write!(f, "global checksum {stored:#06x} mismatch"),
+            Self::SizeMismatch { declared, actual } => {
+                write!(
+                    f,
+                    "header declares {declared} bytes of ROM but the image is {actual} bytes"
+                )
+            }
+            Self::UnknownCartridgeType(code) => write!(f, "unknown cartridge type {code:#04x}"),
+        }
+    }
+}
+
+/// The checksum the boot ROM verifies over 0x0134-0x014C
+pub fn compute_header_checksum(bytes: &[u8]) -> u8 {
+    bytes[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1))
+}
+
+/// The 16 bit sum of every byte in the ROM, except the checksum itself
+pub fn compute_global_checksum(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x014E && *i != 0x014F)
+        .fold(0u16, |acc, (_, b)| acc.wrapping_add(u16::from(*b)))
+}
+
+fn decode_title(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                char::from(*b)
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+fn decode_manufacturer_code(bytes: &[u8]) -> Option<String> {
+    bytes
+        .iter()
+        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        .then(|| bytes.iter().map(|b| char::from(*b)).collect())
+}
+
+const fn rom_size(code: u8) -> usize {
+    match code {
+        0x00..=0x08 => 0x8000 << code,
+        // Unofficial sizes that only appear in a few sources
+        0x52 => 0x0012_0000,
+        0x53 => 0x0014_0000,
+        0x54 => 0x0018_0000,
+        _ => 0,
+    }
+}
+
+const fn ram_size(code: u8) -> usize {
+    match code {
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x0002_0000,
+        0x05 => 0x0001_0000,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbFlag {
+    /// Made before the CGB existed, or without any CGB functions
+    Dmg,
+    /// Supports CGB functions but also works on older models
+    Enhanced,
+    /// Only works on the CGB
+    Only,
+}
+
+impl From<u8> for CgbFlag {
+    fn from(value: u8) -> Self {
+        match value {
+            0x80 => Self::Enhanced,
+            0xC0 => Self::Only,
+            _ => Self::Dmg,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+impl From<u8> for Destination {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => Self::Japan,
+            0x01 => Self::Overseas,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+/// <https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8),
+}
+
+impl From<u8> for CartridgeType {
+    fn from(value: u8) -> Self {
+        use CartridgeType::*;
+        match value {
+            0x00 => RomOnly,
+            0x01 => Mbc1,
+            0x02 => Mbc1Ram,
+            0x03 => Mbc1RamBattery,
+            0x05 => Mbc2,
+            0x06 => Mbc2Battery,
+            0x08 => RomRam,
+            0x09 => RomRamBattery,
+            0x0B => Mmm01,
+            0x0C => Mmm01Ram,
+            0x0D => Mmm01RamBattery,
+            0x0F => Mbc3TimerBattery,
+            0x10 => Mbc3TimerRamBattery,
+            0x11 => Mbc3,
+            0x12 => Mbc3Ram,
+            0x13 => Mbc3RamBattery,
+            0x19 => Mbc5,
+            0x1A => Mbc5Ram,
+            0x1B => Mbc5RamBattery,
+            0x1C => Mbc5Rumble,
+            0x1D => Mbc5RumbleRam,
+            0x1E => Mbc5RumbleRamBattery,
+            0x20 => Mbc6,
+            0x22 => Mbc7SensorRumbleRamBattery,
+            0xFC => PocketCamera,
+            0xFD => BandaiTama5,
+            0xFE => HuC3,
+            0xFF => HuC1RamBattery,
+            _ => Unknown(value),
+        }
+    }
+}
+
+impl CartridgeType {
+    pub const fn has_ram(self) -> bool {
+        use CartridgeType::*;
+        matches!(
+            self,
+            Mbc1Ram
+                | Mbc1RamBattery
+                | Mbc2
+                | Mbc2Battery
+                | RomRam
+                | RomRamBattery
+                | Mmm01Ram
+                | Mmm01RamBattery
+                | Mbc3TimerRamBattery
+                | Mbc3Ram
+                | Mbc3RamBattery
+                | Mbc5Ram
+                | Mbc5RamBattery
+                | Mbc5RumbleRam
+                | Mbc5RumbleRamBattery
+                | Mbc6
+                | Mbc7SensorRumbleRamBattery
+                | PocketCamera
+                | BandaiTama5
+                | HuC3
+                | HuC1RamBattery
+        )
+    }
+
+    pub const fn has_battery(self) -> bool {
+        use CartridgeType::*;
+        matches!(
+            self,
+            Mbc1RamBattery
+                | Mbc2Battery
+                | RomRamBattery
+                | Mmm01RamBattery
+                | Mbc3TimerBattery
+                | Mbc3TimerRamBattery
+                | Mbc3RamBattery
+                | Mbc5RamBattery
+                | Mbc5RumbleRamBattery
+                | Mbc6
+                | Mbc7SensorRumbleRamBattery
+                | PocketCamera
+                | BandaiTama5
+                | HuC3
+                | HuC1RamBattery
+        )
+    }
+
+    pub const fn has_timer(self) -> bool {
+        use CartridgeType::*;
+        matches!(
+            self,
+            Mbc3TimerBattery | Mbc3TimerRamBattery | HuC3 | BandaiTama5
+        )
+    }
+
+    pub const fn has_rumble(self) -> bool {
+        use CartridgeType::*;
+        matches!(
+            self,
+            Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery | Mbc7SensorRumbleRamBattery
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Licensee {
+    /// The single byte code at 0x014B
+    Old(u8),
+    /// The two ASCII characters at 0x0144-0x0145, used when the old code is 0x33
+    New([u8; 2]),
+}
+
+impl Licensee {
+    pub const fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::Old(code) => old_licensee_name(*code),
+            Self::New(code) => new_licensee_name(*code),
+        }
+    }
+}
+
+impl fmt::Display for Licensee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.name(), self) {
+            (Some(name), _) => write!(f, "{name}"),
+            (None, Self::Old(code)) => write!(f, "Unknown ({code:#04x})"),
+            (None, Self::New([a, b])) => {
+                write!(f, "Unknown ({}{})", char::from(*a), char::from(*b))
+            }
+        }
+    }
+}
+
+/// <https://gbdev.io/pandocs/The_Cartridge_Header.html#01440145--new-licensee-code>
+const fn new_licensee_name(code: [u8; 2]) -> Option<&'static str> {
+    Some(match &code {
+        b"00" => "None",
+        b"01" => "Nintendo Research & Development 1",
+        b"08" => "Capcom",
+        b"13" | b"69" => "EA (Electronic Arts)",
+        b"18" | b"38" => "Hudson Soft",
+        b"19" => "B-AI",
+        b"20" => "KSS",
+        b"22" => "Planning Office WADA",
+        b"24" => "PCM Complete",
+        b"25" => "San-X",
+        b"28" => "Kemco",
+        b"29" => "SETA Corporation",
+        b"30" => "Viacom",
+        b"31" => "Nintendo",
+        b"32" => "Bandai",
+        b"33" | b"93" => "Ocean Software/Acclaim Entertainment",
+        b"34" | b"54" => "Konami",
+        b"35" => "HectorSoft",
+        b"37" => "Taito",
+        b"39" => "Banpresto",
+        b"41" => "Ubi Soft",
+        b"42" => "Atlus",
+        b"44" => "Malibu Interactive",
+        b"46" => "Angel",
+        b"47" => "Bullet-Proof Software",
+        b"49" => "Irem",
+        b"50" => "Absolute",
+        b"51" => "Acclaim Entertainment",
+        b"52" => "Activision",
+        b"53" => "Sammy USA Corporation",
+        b"55" => "Hi Tech Expressions",
+        b"56" => "LJN",
+        b"57" => "Matchbox",
+        b"58" => "Mattel",
+        b"59" => "Milton Bradley Company",
+        b"60" => "Titus Interactive",
+        b"61" => "Virgin Games Ltd.",
+        b"64" => "Lucasfilm Games",
+        b"67" => "Ocean Software",
+        b"70" => "Infogrames",
+        b"71" => "Interplay Entertainment",
+        b"72" => "Broderbund",
+        b"73" => "Sculptured Software",
+        b"75" => "The Sales Curve Limited",
+        b"78" => "THQ",
+        b"79" => "Accolade",
+        b"80" => "Misawa Entertainment",
+        b"83" => "lozc",
+        b"86" => "Tokuma Shoten",
+        b"87" => "Tsukuda Original",
+        b"91" => "Chunsoft Co.",
+        b"92" => "Video System",
+        b"95" => "Varie",
+        b"96" => "Yonezawa/s'pal",
+        b"97" => "Kaneko",
+        b"99" => "Pack-In-Video",
+        b"9H" => "Bottom Up",
+        b"A4" => "Konami (Yu-Gi-Oh!)",
+        b"BL" => "MTO",
+        b"DK" => "Kodansha",
+        _ => return None,
+    })
+}
+
+/// <https://gbdev.io/pandocs/The_Cartridge_Header.html#014b--old-licensee-code>
+const fn old_licensee_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "HOT-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "EA (Electronic Arts)",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Games Ltd.",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 | 0x7F | 0x97 | 0xC2 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay Entertainment",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Square",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{make_rom, TestRom};
+
+    #[test]
+    fn test_parse() {
+        let header = TestRom {
+            title: "TETRIS",
+            cartridge_type: 0x13,
+            rom_size: 2,
+            ram_size: 3,
+            old_licensee: 0x01,
+            ..TestRom::default()
+        }
+        .header();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.title_checksum(), 0xDB);
+        assert_eq!(header.cartridge_type, CartridgeType::Mbc3RamBattery);
+        assert!(header.cartridge_type.has_battery());
+        assert_eq!(header.rom_size, 128 * 1024);
+        assert_eq!(header.ram_size, 32 * 1024);
+        assert_eq!(header.licensee.name(), Some("Nintendo"));
+        assert_eq!(header.destination, Destination::Japan);
+        assert!(header.logo_valid);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+    }
+
+    #[test]
+    fn test_cgb_title() {
+        let pokemon = TestRom {
+            title: "POKEMON_SLVAAXE",
+            cartridge_type: 0x10,
+            rom_size: 6,
+            ram_size: 3,
+            cgb_flag: 0x80,
+            old_licensee: 0x33,
+            new_licensee: *b"01",
+        };
+        let header = pokemon.header();
+        assert_eq!(header.title, "POKEMON_SLVAAXE");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb_flag, CgbFlag::Enhanced);
+        assert_eq!(header.licensee, Licensee::New(*b"01"));
+        assert_eq!(header.rom_size, 2 * 1024 * 1024);
+
+        // A full length title with the old licensee code has no manufacturer code
+        let header = TestRom {
+            title: "WARIOLANDDELUXE",
+            old_licensee: 0x01,
+            ..pokemon
+        }
+        .header();
+        assert_eq!(header.title, "WARIOLANDDELUXE");
+        assert_eq!(header.manufacturer_code, None);
+    }
+
+    #[test]
+    fn test_bad_checksums() {
+        let mut rom = make_rom("TEST", 0x00, 0, 0);
+        rom[0x0200] = 0xAA;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+
+        rom[0x0134] = b'X';
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!header.header_checksum_valid);
+
+        assert!(CartridgeHeader::parse(&rom[..0x100]).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let rom = make_rom("TEST", 0x00, 0, 0);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(header.validate(rom.len()).is_clean());
+
+        let mut rom = make_rom("HOMEBREW", 0x42, 1, 0);
+        rom[0x0104..0x0134].fill(0);
+        rom[0x014D] ^= 0xFF;
+        let header = CartridgeHeader::parse(&rom[..0x8000]).unwrap();
+        let report = header.validate(0x8000);
+        assert_eq!(
+            report.problems,
+            [
+                HeaderProblem::InvalidLogo,
+                HeaderProblem::HeaderChecksum(rom[0x014D]),
+                HeaderProblem::GlobalChecksum(header.global_checksum),
+                HeaderProblem::SizeMismatch {
+                    declared: 0x1_0000,
+                    actual: 0x8000
+                },
+                HeaderProblem::UnknownCartridgeType(0x42),
+            ]
+        );
+        assert!(report
+            .to_string()
+            .starts_with("logo doesn't match the boot ROM's, header checksum"));
+    }
+}
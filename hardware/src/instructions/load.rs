@@ -198,7 +198,8 @@ mod tests {
     fn test_load_16bit() {
         let mut cpu = CPU::new();
         let instruction = load_16bit!(LD_SP_D16, 0x31, sp);
-        cpu.mmu.write_word(0x0101, 0x1234);
+        cpu.reg.pc = 0xC000;
+        cpu.mmu.write_word(0xC001, 0x1234);
         instruction.run(&mut cpu);
         assert_eq!(cpu.reg.sp, 0x1234);
     }
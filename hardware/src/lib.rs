@@ -12,14 +12,25 @@
     clippy::cast_possible_truncation
 )]
 #![feature(lazy_cell)]
-use tracing::warn;
-
+pub mod archive;
+pub mod cart;
+pub mod cheats;
 pub mod cpu;
+pub mod dat;
 pub mod emu;
+pub mod header;
 pub mod instructions;
 pub mod mem;
+pub mod palette;
+pub mod patch;
+pub mod ppu;
 pub mod ram;
 pub mod reg;
+pub mod save;
+pub mod screenshot;
+#[cfg(test)]
+mod testing;
+pub mod timer;
 
 pub type Result<T> = anyhow::Result<T>;
 
@@ -28,8 +39,9 @@ pub const LOG_REGISTERS: bool = false;
 pub const CLOCK_FREQ: usize = 4_194_304; // 4.194304 MHz
 pub const MACHINE_FREQ: usize = 1_048_576; // 1.048576 MHz - 1/4 of the clock frequency
 pub const FPS: usize = 60;
+pub const M_CYCLES_PER_FRAME: u32 = 17_556; // 154 lines of 114 machine cycles
 pub const RAM_SIZE: usize = 0x2000;
-pub const MAX_ROM_SIZE: usize = 0x8000; // Assumed from the region size in memory
+pub const MAX_ROM_SIZE: usize = 0x80_0000; // 8 MiB, the most an MBC5 can address
 
 pub const NINTENDO_HEADER: [u8; 48] = [
     0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
@@ -50,26 +62,23 @@ pub fn default<T: Default>() -> T {
     Default::default()
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct GPU {}
-impl GPU {
-    pub fn new() -> Self {
-        warn!("nice gpu nerd");
-        Self {}
-    }
-}
-
-#[allow(unused)]
+/// IE and IF
+///
+/// <https://gbdev.io/pandocs/Interrupts.html>
 #[derive(Debug, Default, Clone, Copy)]
-struct Timer {
-    counter: u8,
-    modulo: u8,
-    control: u8,
+pub struct Interrupts {
+    pub enable: u8,
+    pub flag: u8,
 }
 
-#[allow(unused)]
-#[derive(Debug, Default, Clone, Copy)]
-struct Interrupts {
-    enable: u8,
-    flag: u8,
+impl Interrupts {
+    pub const VBLANK: u8 = 1 << 0;
+    pub const STAT: u8 = 1 << 1;
+    pub const TIMER: u8 = 1 << 2;
+    pub const SERIAL: u8 = 1 << 3;
+    pub const JOYPAD: u8 = 1 << 4;
+
+    pub const fn request(&mut self, interrupt: u8) {
+        self.flag |= interrupt;
+    }
 }
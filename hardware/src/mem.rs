@@ -1,46 +1,111 @@
 #![allow(dead_code)]
-use std::io::ErrorKind::InvalidData;
+use std::sync::Arc;
 use std::sync::RwLock;
-use std::{fs, sync::Arc};
 
-use tracing::{debug, error, info, trace};
+use anyhow::bail;
+use tracing::{debug, error, info, trace, warn};
 
+use crate::cart::{Cartridge, LoadOptions};
+use crate::cheats::Cheats;
+use crate::dat::Dat;
+use crate::ppu::{Ppu, PpuBackend};
 use crate::ram::{MemoryRegion, Region};
-use crate::{ram::RamSize::*, ram::RamStart::*, Interrupts, Timer, BOOT_ROM, NINTENDO_HEADER};
+use crate::timer::Timer;
+use crate::{archive, patch};
+use crate::{ram::RamSize::*, ram::RamStart::*, Interrupts, BOOT_ROM, M_CYCLES_PER_FRAME};
 
 #[derive(Debug, Clone, Default)]
 pub struct MMU {
-    cart: Region,
-    vram: Region,
+    cart: Arc<RwLock<Cartridge>>,
+    ppu: Ppu,
     wram: Region,
     hram: Region,
     timer: Timer,
     // https://gbdev.io/pandocs/Joypad_Input.html#ff00--p1joyp-joypad
     joypad: u8,
-    // https://gbdev.io/pandocs/Timer_and_Divider_Registers.html#ff04--div-divider-register
-    divider_reg: u8,
     // https://gbdev.io/pandocs/Interrupts.html#ff0f--if-interrupt-flag
     interrupts: Interrupts,
+    /// The last value written to DMA, which starts an OAM transfer
+    dma: u8,
+    cheats: Cheats,
+    /// Machine cycles into the current frame
+    frame_cycles: u32,
+    /// Where the instruction being run started, for diagnostics
+    pc: u16,
+    /// Log accesses to VRAM and OAM that the PPU is blocking
+    warn_blocked_access: bool,
 }
 
 impl MMU {
     pub fn new() -> Self {
         Self {
-            cart: MemoryRegion::new(u16::from(KB32), u16::from(Cart)),
-            vram: MemoryRegion::new(u16::from(KB8), u16::from(VRam)),
+            cart: Arc::new(RwLock::new(Cartridge::default())),
+            ppu: Ppu::default(),
             wram: MemoryRegion::new(u16::from(KB8), u16::from(WRam)),
             hram: MemoryRegion::new(126, u16::from(HRam)),
             timer: Timer::default(),
             joypad: 0,
-            divider_reg: 0,
             interrupts: Interrupts::default(),
+            dma: 0,
+            cheats: Cheats::default(),
+            frame_cycles: 0,
+            pc: 0,
+            warn_blocked_access: false,
+        }
+    }
+
+    pub fn with_ppu_backend(backend: PpuBackend) -> Self {
+        Self {
+            ppu: Ppu::new(backend),
+            ..Self::new()
         }
     }
 }
 
 #[allow(unused)]
 impl MMU {
+    pub const fn cartridge(&self) -> &Arc<RwLock<Cartridge>> {
+        &self.cart
+    }
+
+    pub const fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub const fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub const fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub const fn set_warn_blocked_access(&mut self, warn: bool) {
+        self.warn_blocked_access = warn;
+    }
+
+    pub const fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub const fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
     pub fn read(&self, address: u16) -> u8 {
+        if is_cart_address(address) {
+            let mut data = self.cart.read().map_or(0, |cart| cart.read(address));
+            if address < 0x8000 {
+                data = self.cheats.patch_read(address, data);
+            }
+            debug!("read {:#04x} from {:#04x}", data, address);
+            return data;
+        }
+
+        if is_device_address(address) {
+            return self.read_device(address);
+        }
+
         self.get_region(address)
             .map_or(0, |lock| match lock.read() {
                 Ok(region) => {
@@ -54,37 +119,138 @@ impl MMU {
 
     pub fn write(&mut self, address: u16, value: u8) {
         debug!("write: {:#04x} {:#04x}", address, value);
+        if is_cart_address(address) {
+            self.cart.write().unwrap().write(address, value);
+            return;
+        }
+
+        if is_device_address(address) {
+            self.write_device(address, value);
+            return;
+        }
+
         if let Some(lock) = self.get_region(address) {
             let mut region = lock.write().unwrap();
             region.write(address, value);
         }
     }
 
+    fn read_device(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0x9FFF if self.ppu.vram_accessible() => self.ppu.read_vram(address),
+            0xFE00..=0xFE9F if self.ppu.oam_accessible() => self.ppu.read_oam(address),
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => {
+                self.blocked_access("read", address);
+                0xFF
+            }
+            0xFF04..=0xFF07 => self.timer.read(address),
+            0xFF46 => self.dma,
+            0xFF40..=0xFF4B => self.ppu.read_register(address),
+            0xFF0F => self.interrupts.flag | 0xE0,
+            0xFFFF => self.interrupts.enable,
+            _ => unreachable!("{address:#06x} is not backed by a device"),
+        }
+    }
+
+    fn write_device(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF if self.ppu.vram_accessible() => {
+                self.ppu.write_vram(address, value);
+            }
+            0xFE00..=0xFE9F if self.ppu.oam_accessible() => self.ppu.write_oam(address, value),
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => self.blocked_access("write", address),
+            0xFF04..=0xFF07 => self.timer.write(address, value),
+            0xFF46 => self.oam_dma(value),
+            0xFF40..=0xFF4B => {
+                let interrupts = self.ppu.write_register(address, value);
+                self.interrupts.request(interrupts);
+            }
+            0xFF0F => self.interrupts.flag = value & 0x1F,
+            0xFFFF => self.interrupts.enable = value,
+            _ => unreachable!("{address:#06x} is not backed by a device"),
+        }
+    }
+
+    fn blocked_access(&self, access: &str, address: u16) {
+        if self.warn_blocked_access {
+            warn!(
+                "{access} of {address:#06x} at pc {:#06x} during mode {}, which hardware ignores",
+                self.pc,
+                self.ppu.mode() as u8
+            );
+        }
+    }
+
+    /// Copies 0xXX00-0xXX9F to OAM.
+    ///
+    /// This happens all at once, rather than over the 160 machine cycles it takes.
+    fn oam_dma(&mut self, source: u8) {
+        self.dma = source;
+        let start = u16::from(source) << 8;
+        for offset in 0..0xA0 {
+            // DMA isn't a CPU access, so the PPU's mode doesn't block it
+            let value = match start + offset {
+                address @ 0x8000..=0x9FFF => self.ppu.read_vram(address),
+                address @ 0xFE00..=0xFE9F => self.ppu.read_oam(address),
+                address => self.read(address),
+            };
+            self.ppu.write_oam(0xFE00 + offset, value);
+        }
+    }
+
     // TODO: This won't have to return Option once we have all the devices implemented.
     #[rustfmt::skip]
     fn get_region(&self, address: u16) -> Option<Arc<RwLock<MemoryRegion>>> {
         match address {
-            0x0000..=0x7FFF => Some(Arc::clone(&self.cart)),
-            0x8000..=0x9FFF => Some(Arc::clone(&self.vram)),
-            0xA000..=0xBFFF => { error!(address, "Cartridge external RAM is not implemented"); None }
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => unreachable!("the cartridge is not a memory region"),
             0xC000..=0xDFFF => Some(Arc::clone(&self.wram)),
             0xE000..=0xFDFF => { error!(address, "Echo RAM is not implemented"); None }
-            0xFE00..=0xFE9F => { error!(address, "Object attribute memory is not implemented"); None }
             0xFEA0..=0xFEFF => { error!(address, "Not usable"); None }
             0xFF00 => { error!(address, "Joypad is not implemented"); None }
-            0xFF04 => { error!(address, "Divider register is not implemented"); None }
-            0xFF05..=0xFF07 => { error!(address, "Timer is not implemented"); None }
-            0xFF0F => { error!(address, "Interrupt flag is not implemented"); None }
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => unreachable!("VRAM and OAM belong to the PPU"),
+            0xFF04..=0xFF07 | 0xFF0F | 0xFF40..=0xFF4B | 0xFFFF => {
+                unreachable!("registers are not memory regions")
+            }
             0xFF10..=0xFF26 => { error!(address, "Sound control registers are not implemented"); None }
             0xFF00..=0xFF7F => { error!(address, "I/O registers are not implemented"); None }
             0xFF80..=0xFFFE => Some(Arc::clone(&self.hram)),
-            0xFFFF => { error!(address, "Interrupt enable is not implemented"); None }
             _ => panic!(
                 "Tried to get device at {address:x} (outside of address space)"
             ),
         }
     }
 
+    /// Advances the devices on the bus by `m_cycles` machine cycles
+    pub fn tick(&mut self, m_cycles: u16) {
+        self.cart.write().unwrap().tick(u32::from(m_cycles));
+
+        for _ in 0..m_cycles {
+            if self.timer.tick() {
+                self.interrupts.request(Interrupts::TIMER);
+            }
+        }
+
+        let interrupts = self.ppu.tick(u32::from(m_cycles) * 4);
+        self.interrupts.request(interrupts);
+
+        self.frame_cycles += u32::from(m_cycles);
+        if self.frame_cycles >= M_CYCLES_PER_FRAME {
+            self.frame_cycles -= M_CYCLES_PER_FRAME;
+            self.end_frame();
+        }
+    }
+
+    /// Applies the GameShark codes, which the real device does during VBlank
+    fn end_frame(&mut self) {
+        let writes: Vec<_> = self.cheats.frame_writes().collect();
+        for (bank, address, value) in writes {
+            match bank {
+                Some(bank) => self.cart.write().unwrap().poke_ram(bank, address, value),
+                None => self.write(address, value),
+            }
+        }
+    }
+
     pub fn read_word(&self, address: u16) -> u16 {
         let upper = self.read(address);
         let lower = self.read(address + 1);
@@ -102,6 +268,10 @@ impl MMU {
     }
 
     pub fn read_range(&self, start: u16, end: u16) -> Option<Vec<u8>> {
+        if is_cart_address(start) || is_device_address(start) {
+            return Some((start..end).map(|address| self.read(address)).collect());
+        }
+
         let region = self.get_region(start);
 
         region.map(|region| {
@@ -113,28 +283,76 @@ impl MMU {
     }
 }
 
-pub fn load_rom(rom: &str, mmu: &MMU) -> std::io::Result<()> {
-    let bytes = fs::read(rom)?;
+pub fn load_rom(rom: &str, options: &LoadOptions, mmu: &MMU) -> crate::Result<()> {
+    let mut bytes = archive::read_rom(rom, options.archive_entry.as_deref())?;
+    // DATs list the original dumps, so identify the game before it's patched
+    let identity = match &options.dat {
+        Some(dat) => Dat::load(dat)?.identify(&bytes),
+        None => None,
+    };
+
+    let patch_file = options
+        .patch
+        .clone()
+        .or_else(|| options.auto_patch.then(|| patch::find_patch(rom)).flatten());
+    if let Some(patch_file) = patch_file {
+        bytes = patch::apply_file(&bytes, patch_file)?;
+    }
 
-    if bytes.len() < 0x0133 || bytes[0x0104..=0x0133] != NINTENDO_HEADER {
+    let mut cart = Cartridge::with_options(bytes, options)?;
+    match &identity {
+        Some(identity) => info!("Identified as {identity}"),
+        None if options.dat.is_some() => warn!("ROM is not in the DAT"),
+        None => {}
+    }
+    cart.identity = identity;
+    let header = &cart.header;
+
+    let report = header.validate(cart.rom().len());
+    for problem in &report.problems {
+        warn!("{problem}");
+    }
+    if options.strict && !report.is_clean() {
         error!("Invalid ROM");
-        return Err(std::io::Error::new(InvalidData, "Invalid ROM"));
+        bail!("Invalid ROM: {report}");
     }
 
     info!("Loading ROM {rom}");
-    let arc = mmu.cart.clone();
-    arc.write()
-        .unwrap()
-        .write_range(0, bytes.len() as u16, &bytes);
+    info!(
+        "{} ({:?}, {} KiB ROM, {} KiB RAM, {})",
+        header.title,
+        header.cartridge_type,
+        header.rom_size / 1024,
+        header.ram_size / 1024,
+        header.licensee
+    );
+
+    *mmu.cart.write().unwrap() = cart;
 
     Ok(())
 }
 
 pub fn load_boot_rom(mmu: &MMU) {
     trace!("Loading boot ROM");
-    let arc = mmu.cart.clone();
-    let mut cart = arc.write().unwrap();
-    cart.write_range(0, BOOT_ROM.len() as u16, BOOT_ROM);
+    mmu.cart.write().unwrap().overlay_rom(BOOT_ROM);
+}
+
+const fn is_cart_address(address: u16) -> bool {
+    matches!(address, 0x0000..=0x7FFF | 0xA000..=0xBFFF)
+}
+
+/// Addresses backed by a device rather than a memory region: VRAM and OAM,
+/// which the PPU can lock, and the I/O registers that are implemented
+const fn is_device_address(address: u16) -> bool {
+    matches!(
+        address,
+        0x8000..=0x9FFF
+            | 0xFE00..=0xFE9F
+            | 0xFF04..=0xFF07
+            | 0xFF0F
+            | 0xFF40..=0xFF4B
+            | 0xFFFF
+    )
 }
 
 #[cfg(test)]
@@ -145,6 +363,7 @@ mod test {
     fn test_mmu() {
         let mut mmu = MMU::new();
 
+        // Writes to ROM go to the (absent) bank controller
         mmu.write_word(0x0000, 0x0001);
         mmu.write_word(0x0002, 0x0203);
 
@@ -154,8 +373,8 @@ mod test {
         mmu.write_word(0xC000, 0x0809);
         mmu.write_word(0xC002, 0x0A0B);
 
-        assert_eq!(mmu.read_word(0x0000), 0x0001);
-        assert_eq!(mmu.read_word(0x0002), 0x0203);
+        assert_eq!(mmu.read_word(0x0000), 0x0000);
+        assert_eq!(mmu.read_word(0x0002), 0x0000);
 
         assert_eq!(mmu.read_word(0x8000), 0x0405);
         assert_eq!(mmu.read_word(0x8002), 0x0607);
@@ -163,4 +382,137 @@ mod test {
         assert_eq!(mmu.read_word(0xC000), 0x0809);
         assert_eq!(mmu.read_word(0xC002), 0x0A0B);
     }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut mmu = MMU::new();
+        mmu.write(0xFFFF, Interrupts::TIMER);
+        mmu.write(0xFF07, 0x05);
+        mmu.write(0xFF05, 0xFF);
+        assert_eq!(mmu.read(0xFF0F), 0xE0);
+
+        mmu.tick(5);
+        assert_eq!(mmu.read(0xFF0F), 0xE0 | Interrupts::TIMER);
+        assert_eq!(mmu.read(0xFFFF), Interrupts::TIMER);
+
+        mmu.write(0xFF0F, 0x00);
+        assert_eq!(mmu.read(0xFF0F), 0xE0);
+    }
+
+    #[test]
+    fn test_blocked_access() {
+        let mut mmu = MMU::new();
+        mmu.set_warn_blocked_access(true);
+        mmu.write(0x8000, 0x12);
+        mmu.write(0xFE00, 0x34);
+        mmu.write(0xFF40, 0x91);
+
+        // OAM scan, once past the first line which skips it
+        mmu.tick(114 + 1);
+        assert_eq!(mmu.read(0x8000), 0x12);
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+        mmu.write(0xFE00, 0x56);
+
+        // Drawing
+        mmu.tick(114 + 20);
+        assert_eq!(mmu.read(0x8000), 0xFF);
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+        mmu.write(0x8000, 0x78);
+
+        // HBlank
+        mmu.tick(43);
+        assert_eq!(mmu.read(0x8000), 0x12);
+        assert_eq!(mmu.read(0xFE00), 0x34);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut mmu = MMU::new();
+        for i in 0..0xA0 {
+            mmu.write(0xC100 + i, i as u8);
+        }
+        mmu.write(0xFF46, 0xC1);
+        assert_eq!(mmu.read(0xFF46), 0xC1);
+        assert_eq!(mmu.read(0xFE00), 0x00);
+        assert_eq!(mmu.read(0xFE9F), 0x9F);
+        assert_eq!(mmu.ppu().oam()[0x42], 0x42);
+
+        // From VRAM while the PPU is drawing
+        mmu.write(0x8000, 0x12);
+        mmu.write(0xFF40, 0x91);
+        mmu.tick(2 * 114 + 21);
+        assert_eq!(mmu.ppu().mode(), crate::ppu::Mode::Drawing);
+        mmu.write(0xFF46, 0x80);
+        assert_eq!(mmu.ppu().oam()[0], 0x12);
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut mmu = MMU::new();
+        mmu.cheats_mut().add("ROM", "3E1-DAF").unwrap();
+        mmu.cheats_mut().add("RAM", "0105ADD2").unwrap();
+        assert_eq!(mmu.read(0x01DA), 0x3E);
+        assert_eq!(mmu.read(0x01DB), 0x00);
+
+        mmu.tick(M_CYCLES_PER_FRAME as u16 - 1);
+        assert_eq!(mmu.read(0xD2AD), 0x00);
+        mmu.tick(1);
+        assert_eq!(mmu.read(0xD2AD), 0x05);
+    }
+
+    #[test]
+    fn test_load_rom_without_logo() {
+        let mut rom = crate::testing::make_rom("HOMEBREW", 0x00, 0, 0);
+        rom[0x0104..0x0134].fill(0);
+        rom[0x0150] = 0x42;
+        let path = crate::testing::temp_path("logo.gb");
+        std::fs::write(&path, rom).unwrap();
+        let path = path.to_str().unwrap();
+
+        let mmu = MMU::new();
+        let strict = LoadOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(load_rom(path, &strict, &mmu).is_err());
+        assert_eq!(mmu.read(0x0150), 0x00);
+
+        load_rom(path, &LoadOptions::default(), &mmu).unwrap();
+        assert_eq!(mmu.read(0x0150), 0x42);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_identify_before_patching() {
+        let rom = crate::testing::make_rom("PATCHED", 0x00, 0, 0);
+        let hashes = crate::dat::RomHashes::compute(&rom);
+        let rom_path = crate::testing::temp_path("patched.gb");
+        std::fs::write(&rom_path, rom).unwrap();
+
+        let dat = crate::testing::temp_path("patched.dat");
+        let xml = format!(
+            r#"<datafile><game name="Patched (World)"><rom size="{}" crc="{:08X}"/></game></datafile>"#,
+            hashes.size, hashes.crc32
+        );
+        std::fs::write(&dat, xml).unwrap();
+
+        // An IPS patch setting 0x0150 to 0x42
+        let patch = crate::testing::temp_path("patched.ips");
+        std::fs::write(&patch, b"PATCH\x00\x01\x50\x00\x01\x42EOF").unwrap();
+
+        let mmu = MMU::new();
+        let options = LoadOptions {
+            patch: Some(patch.clone()),
+            dat: Some(dat.clone()),
+            ..Default::default()
+        };
+        load_rom(rom_path.to_str().unwrap(), &options, &mmu).unwrap();
+        assert_eq!(mmu.read(0x0150), 0x42);
+        let identity = mmu.cartridge().read().unwrap().identity.clone();
+        assert_eq!(identity.unwrap().name(), "Patched (World)");
+
+        for path in [rom_path, dat, patch] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
 }
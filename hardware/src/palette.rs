@@ -0,0 +1,416 @@
+//! Colors for the DMG's four shades
+//!
+//! <https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes>
+use std::fmt;
+use std::str::FromStr;
+
+use crate::header::{CartridgeHeader, Licensee};
+
+pub type Rgb = [u8; 3];
+
+/// Where a pixel came from, since a colorized game has a palette for each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// The background and window
+    Background = 0,
+    Obj0 = 1,
+    Obj1 = 2,
+}
+
+impl Layer {
+    /// Packs a shade with the layer it's from, as the PPU's framebuffer stores it
+    pub const fn tag(self, shade: u8) -> u8 {
+        (self as u8) << 2 | shade
+    }
+}
+
+/// A color for each shade of each layer, from white to black
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub bg: [Rgb; 4],
+    pub obj0: [Rgb; 4],
+    pub obj1: [Rgb; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::GRAYSCALE
+    }
+}
+
+impl Palette {
+    pub const GRAYSCALE: Self = Self::uniform([
+        [0xFF, 0xFF, 0xFF],
+        [0xAA, 0xAA, 0xAA],
+        [0x55, 0x55, 0x55],
+        [0x00, 0x00, 0x00],
+    ]);
+    /// The original DMG's green
+    pub const GREEN: Self = Self::uniform([
+        [0x9B, 0xBC, 0x0F],
+        [0x8B, 0xAC, 0x0F],
+        [0x30, 0x62, 0x30],
+        [0x0F, 0x38, 0x0F],
+    ]);
+    /// The Game Boy Pocket's grey
+    pub const POCKET: Self = Self::uniform([
+        [0xC4, 0xCF, 0xA1],
+        [0x8B, 0x95, 0x6D],
+        [0x4D, 0x53, 0x3C],
+        [0x1F, 0x1F, 0x1F],
+    ]);
+    /// The Game Boy Light's backlight
+    pub const LIGHT: Self = Self::uniform([
+        [0x00, 0xB5, 0x81],
+        [0x00, 0x9A, 0x71],
+        [0x00, 0x69, 0x4A],
+        [0x00, 0x4F, 0x3B],
+    ]);
+
+    pub const NAMES: [(Self, &'static str); 4] = [
+        (Self::GRAYSCALE, "grayscale"),
+        (Self::GREEN, "green"),
+        (Self::POCKET, "pocket"),
+        (Self::LIGHT, "light"),
+    ];
+
+    /// The same colors for the background and objects
+    pub const fn uniform(colors: [Rgb; 4]) -> Self {
+        Self {
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        }
+    }
+
+    pub const fn colors(&self, layer: Layer) -> &[Rgb; 4] {
+        match layer {
+            Layer::Background => &self.bg,
+            Layer::Obj0 => &self.obj0,
+            Layer::Obj1 => &self.obj1,
+        }
+    }
+
+    /// Converts pixels tagged with [`Layer::tag`] to RGBA
+    pub fn to_rgba(&self, pixels: &[u8]) -> Vec<u8> {
+        pixels
+            .iter()
+            .flat_map(|&pixel| {
+                let layer = match pixel >> 2 {
+                    1 => Layer::Obj0,
+                    2 => Layer::Obj1,
+                    _ => Layer::Background,
+                };
+                let [r, g, b] = self.colors(layer)[usize::from(pixel & 0x03)];
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    /// The palette the CGB boot ROM picks for a DMG game
+    pub fn colorize(header: &CartridgeHeader) -> Self {
+        let checksum = header.title_checksum();
+        let fourth = header.raw_title[3];
+        let licensed = matches!(
+            header.licensee,
+            Licensee::Old(0x01) | Licensee::New([b'0', b'1'])
+        );
+
+        COLORIZATION
+            .iter()
+            .find(|entry| {
+                licensed && entry.checksum == checksum && entry.fourth.is_none_or(|c| c == fourth)
+            })
+            .map_or(DEFAULT_COLORIZATION, |entry| entry.palette)
+    }
+}
+
+const WHITE: Rgb = [0xFF, 0xFF, 0xFF];
+const BLACK: Rgb = [0x00, 0x00, 0x00];
+const RED: [Rgb; 4] = [WHITE, [0xFF, 0x84, 0x84], [0x94, 0x3A, 0x3A], BLACK];
+const BLUE: [Rgb; 4] = [WHITE, [0x63, 0xA5, 0xFF], [0x00, 0x00, 0xFF], BLACK];
+
+/// What the CGB boot ROM uses for games it doesn't recognise
+const DEFAULT_COLORIZATION: Palette = Palette {
+    bg: [WHITE, [0x7B, 0xFF, 0x31], [0x00, 0x63, 0xC5], BLACK],
+    obj0: RED,
+    obj1: RED,
+};
+
+struct Colorization {
+    checksum: u8,
+    /// Breaks ties between titles with the same checksum
+    fourth: Option<u8>,
+    palette: Palette,
+}
+
+/// Some of the CGB boot ROM's table, which only covers Nintendo's own games
+const COLORIZATION: &[Colorization] = &[
+    // TETRIS
+    Colorization {
+        checksum: 0xDB,
+        fourth: None,
+        palette: Palette::uniform([WHITE, [0xFF, 0xFF, 0x00], [0xFF, 0x00, 0x00], BLACK]),
+    },
+    // POKEMON RED
+    Colorization {
+        checksum: 0x14,
+        fourth: None,
+        palette: Palette {
+            bg: RED,
+            obj0: [WHITE, [0x7B, 0xFF, 0x31], [0x00, 0x84, 0x00], BLACK],
+            obj1: RED,
+        },
+    },
+    // POKEMON BLUE
+    Colorization {
+        checksum: 0x61,
+        fourth: Some(b'E'),
+        palette: Palette {
+            bg: BLUE,
+            obj0: RED,
+            obj1: BLUE,
+        },
+    },
+    // SUPER MARIOLAND
+    Colorization {
+        checksum: 0x46,
+        fourth: Some(b'E'),
+        palette: Palette {
+            bg: [WHITE, [0xAD, 0xAD, 0x84], [0x42, 0x73, 0x7B], BLACK],
+            obj0: [WHITE, [0xFF, 0x73, 0x00], [0x94, 0x42, 0x00], BLACK],
+            obj1: [
+                WHITE,
+                [0x5A, 0xBD, 0xFF],
+                [0xFF, 0x00, 0x00],
+                [0x00, 0x00, 0xFF],
+            ],
+        },
+    },
+    // DONKEY KONG
+    Colorization {
+        checksum: 0x19,
+        fourth: None,
+        palette: Palette {
+            bg: [
+                [0xFF, 0xFF, 0x9C],
+                [0x94, 0xB5, 0xFF],
+                [0x63, 0x94, 0x73],
+                [0x00, 0x3A, 0x3A],
+            ],
+            obj0: [
+                [0xFF, 0xC5, 0x42],
+                [0xFF, 0xD6, 0x00],
+                [0x94, 0x3A, 0x00],
+                [0x4A, 0x00, 0x00],
+            ],
+            obj1: RED,
+        },
+    },
+];
+
+/// Which palette to draw a game with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteChoice {
+    Fixed(Palette),
+    /// Pick one from the title, like a CGB does
+    Colorize,
+}
+
+impl Default for PaletteChoice {
+    fn default() -> Self {
+        Self::Fixed(Palette::default())
+    }
+}
+
+impl PaletteChoice {
+    pub fn resolve(&self, header: &CartridgeHeader) -> Palette {
+        match self {
+            Self::Fixed(palette) => *palette,
+            Self::Colorize => Palette::colorize(header),
+        }
+    }
+}
+
+impl FromStr for PaletteChoice {
+    type Err = String;
+
+    /// A built-in name, `auto`, or comma separated hex colors: four for
+    /// everything, or twelve for the background, OBP0 and OBP1 in turn
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Colorize);
+        }
+        if let Some(&(palette, _)) = Palette::NAMES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(Self::Fixed(palette));
+        }
+
+        let colors = s
+            .split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let shades = |start: usize| {
+            [
+                colors[start],
+                colors[start + 1],
+                colors[start + 2],
+                colors[start + 3],
+            ]
+        };
+        match colors.len() {
+            4 => Ok(Self::Fixed(Palette::uniform(shades(0)))),
+            12 => Ok(Self::Fixed(Palette {
+                bg: shades(0),
+                obj0: shades(4),
+                obj1: shades(8),
+            })),
+            _ => {
+                let names: Vec<_> = Palette::NAMES.iter().map(|(_, name)| *name).collect();
+                Err(format!(
+                    "expected auto, {}, or 4 or 12 hex colors, got {s:?}",
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+impl fmt::Display for PaletteChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Colorize => f.write_str("auto"),
+            Self::Fixed(palette) => {
+                if let Some((_, name)) = Palette::NAMES.iter().find(|(p, _)| p == palette) {
+                    return f.write_str(name);
+                }
+                let colors: Vec<_> = [palette.bg, palette.obj0, palette.obj1]
+                    .iter()
+                    .flatten()
+                    .map(|[r, g, b]| format!("#{r:02x}{g:02x}{b:02x}"))
+                    .collect();
+                f.write_str(&colors.join(","))
+            }
+        }
+    }
+}
+
+/// `#rrggbb` or `rrggbb`
+fn parse_color(s: &str) -> Result<Rgb, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        return Err(format!("invalid color {s:?}, expected #rrggbb"));
+    }
+    let value = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid color {s:?}"))?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok([r, g, b])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TestRom;
+
+    #[test]
+    fn test_to_rgba() {
+        let palette = PaletteChoice::from_str("#000000,#111111,#222222,#333333,444444,555555,666666,777777,888888,999999,aaaaaa,bbbbbb")
+            .unwrap()
+            .resolve(&TestRom::default().header());
+        let pixels = [
+            Layer::Background.tag(0),
+            Layer::Background.tag(3),
+            Layer::Obj0.tag(1),
+            Layer::Obj1.tag(2),
+        ];
+        assert_eq!(
+            palette.to_rgba(&pixels),
+            [
+                0x00, 0x00, 0x00, 0xFF, 0x33, 0x33, 0x33, 0xFF, 0x55, 0x55, 0x55, 0xFF, 0xAA, 0xAA,
+                0xAA, 0xFF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("Green".parse(), Ok(PaletteChoice::Fixed(Palette::GREEN)));
+        assert_eq!("auto".parse(), Ok(PaletteChoice::Colorize));
+        assert_eq!(
+            "#ffffff, #aaaaaa, #555555, #000000".parse(),
+            Ok(PaletteChoice::Fixed(Palette::GRAYSCALE))
+        );
+        assert!("#ffffff,#aaaaaa,#555555".parse::<PaletteChoice>().is_err());
+        assert!("#fffff,#aaaaaa,#555555,#000000"
+            .parse::<PaletteChoice>()
+            .is_err());
+        assert!("purple".parse::<PaletteChoice>().is_err());
+
+        for choice in [
+            PaletteChoice::Colorize,
+            PaletteChoice::Fixed(Palette::LIGHT),
+            "#010203,#040506,#070809,#0a0b0c".parse().unwrap(),
+        ] {
+            assert_eq!(choice.to_string().parse(), Ok(choice));
+        }
+    }
+
+    #[test]
+    fn test_colorize() {
+        let nintendo = TestRom {
+            old_licensee: 0x01,
+            ..TestRom::default()
+        };
+        let tetris = TestRom {
+            title: "TETRIS",
+            ..nintendo
+        }
+        .header();
+        assert_eq!(tetris.title_checksum(), 0xDB);
+        assert_eq!(Palette::colorize(&tetris).bg[1], [0xFF, 0xFF, 0x00]);
+        assert_eq!(
+            PaletteChoice::Colorize.resolve(&tetris).bg[1],
+            [0xFF, 0xFF, 0x00]
+        );
+        assert_eq!(
+            PaletteChoice::default().resolve(&tetris),
+            Palette::GRAYSCALE
+        );
+
+        // Same checksum, told apart by the fourth letter
+        let blue = TestRom {
+            title: "POKEMON BLUE",
+            ..nintendo
+        }
+        .header();
+        assert_eq!(Palette::colorize(&blue).bg, BLUE);
+        let mut other = blue;
+        other.raw_title[3] = b'F';
+        other.raw_title[4] = b'L';
+        assert_eq!(Palette::colorize(&other), DEFAULT_COLORIZATION);
+
+        // Only Nintendo's games are recognised
+        assert_eq!(
+            Palette::colorize(
+                &TestRom {
+                    title: "TETRIS",
+                    old_licensee: 0x0A,
+                    ..TestRom::default()
+                }
+                .header()
+            ),
+            DEFAULT_COLORIZATION
+        );
+        assert_eq!(
+            Palette::colorize(
+                &TestRom {
+                    title: "UNKNOWN",
+                    ..nintendo
+                }
+                .header()
+            ),
+            DEFAULT_COLORIZATION
+        );
+    }
+}
@@ -0,0 +1,443 @@
+//! Soft patches applied to the ROM image before it's loaded
+//!
+//! IPS: <https://zerosoft.zophar.net/ips.php>
+//! BPS: <https://www.romhacking.net/documents/746/>
+//! UPS: <https://www.romhacking.net/documents/392/>
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+use tracing::info;
+
+use crate::MAX_ROM_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const UPS_MAGIC: &[u8] = b"UPS1";
+/// Source, target and patch CRC32s at the end of BPS and UPS patches
+const FOOTER_SIZE: usize = 12;
+const EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+/// Enough bytes for any number that fits a `usize`
+const MAX_VARINT_BYTES: usize = 10;
+
+/// The patch next to `rom` with the same name, like `game.ips` for `game.gb`
+pub fn find_patch(rom: impl AsRef<Path>) -> Option<PathBuf> {
+    let rom = rom.as_ref();
+    EXTENSIONS
+        .iter()
+        .map(|ext| rom.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// Applies `patch` to `rom`, telling the format from its magic bytes
+pub fn apply(rom: &[u8], patch: &[u8]) -> crate::Result<Vec<u8>> {
+    let patched = if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else {
+        bail!("Not an IPS, BPS or UPS patch");
+    };
+
+    patched.context("Failed to apply patch")
+}
+
+pub fn apply_file(rom: &[u8], patch: impl AsRef<Path>) -> crate::Result<Vec<u8>> {
+    let patch = patch.as_ref();
+    info!("Applying patch {}", patch.display());
+    let bytes =
+        std::fs::read(patch).with_context(|| format!("Failed to read {}", patch.display()))?;
+    apply(rom, &bytes)
+}
+
+/// Reads through a patch, failing instead of panicking when it's cut short
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    const fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .context("Patch length overflows")?;
+        let Some(bytes) = self.data.get(self.pos..end) else {
+            bail!("Patch ends early at {:#x}", self.data.len());
+        };
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> crate::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, len: usize) -> crate::Result<usize> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |acc, b| acc << 8 | usize::from(*b)))
+    }
+
+    /// The variable length integers of BPS and UPS, where every byte but the
+    /// last also adds one to the next
+    fn varint(&mut self) -> crate::Result<usize> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        for _ in 0..MAX_VARINT_BYTES {
+            let byte = self.byte()?;
+            value = usize::from(byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .context("Patch number overflows")?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).context("Patch number overflows")?;
+            value = value.checked_add(shift).context("Patch number overflows")?;
+        }
+        bail!("Patch number is longer than {MAX_VARINT_BYTES} bytes")
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+
+    loop {
+        if reader.bytes(3)? == IPS_EOF {
+            break;
+        }
+        reader.pos -= 3;
+
+        let offset = reader.be(3)?;
+        let (len, data) = match reader.be(2)? {
+            // Run length encoded record
+            0 => {
+                let len = reader.be(2)?;
+                (len, None)
+            }
+            len => (len, Some(reader.bytes(len)?)),
+        };
+
+        let end = offset.checked_add(len).context("Patch offset overflows")?;
+        ensure!(
+            end <= MAX_ROM_SIZE,
+            "Patch writes past {MAX_ROM_SIZE} bytes, larger than any ROM"
+        );
+        if out.len() < end {
+            out.resize(end, 0);
+        }
+        match data {
+            Some(data) => out[offset..end].copy_from_slice(data),
+            None => out[offset..end].fill(reader.byte()?),
+        }
+    }
+
+    // Some patchers append the size to truncate to
+    if let Ok(size) = reader.be(3) {
+        out.truncate(size);
+    }
+
+    Ok(out)
+}
+
+/// Checks the CRC32s in a BPS or UPS footer, returning the target's
+fn check_footer(rom: &[u8], patch: &[u8]) -> crate::Result<u32> {
+    if patch.len() < FOOTER_SIZE {
+        bail!("Patch is too short for its checksums");
+    }
+
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let crc = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+
+    let patch_crc = crc32fast::hash(&patch[..patch.len() - 4]);
+    if patch_crc != crc(8) {
+        bail!("Patch checksum is {patch_crc:08x}, expected {:08x}", crc(8));
+    }
+
+    let source_crc = crc32fast::hash(rom);
+    if source_crc != crc(0) {
+        bail!(
+            "Patch is for a ROM with CRC32 {:08x}, this one is {source_crc:08x}",
+            crc(0)
+        );
+    }
+
+    Ok(crc(4))
+}
+
+fn check_target_size(size: usize) -> crate::Result<()> {
+    ensure!(
+        size <= MAX_ROM_SIZE,
+        "Patch makes a {size} byte ROM, larger than the {MAX_ROM_SIZE} bytes any mapper can address"
+    );
+    Ok(())
+}
+
+fn check_target(out: &[u8], expected: u32) -> crate::Result<()> {
+    let crc = crc32fast::hash(out);
+    if crc != expected {
+        bail!("Patched ROM has CRC32 {crc:08x}, expected {expected:08x}");
+    }
+    Ok(())
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> crate::Result<Vec<u8>> {
+    let target_crc = check_footer(rom, patch)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = Reader::new(&patch[..end], BPS_MAGIC.len());
+
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        bail!(
+            "Patch is for a {source_size} byte ROM, this one is {} bytes",
+            rom.len()
+        );
+    }
+
+    check_target_size(target_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    while reader.pos < end {
+        let data = reader.varint()?;
+        let len = (data >> 2) + 1;
+        ensure!(
+            out.len()
+                .checked_add(len)
+                .is_some_and(|end| end <= target_size),
+            "Patch writes past its {target_size} byte target"
+        );
+        match data & 3 {
+            // Source read
+            0 => {
+                let start = out.len();
+                let Some(bytes) = rom.get(start..start + len) else {
+                    bail!("Source read past the end of the ROM");
+                };
+                out.extend_from_slice(bytes);
+            }
+            // Target read
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // Source copy
+            2 => {
+                source_offset = relative(source_offset, reader.varint()?)?;
+                let end = source_offset
+                    .checked_add(len)
+                    .context("Copy offset out of range")?;
+                let Some(bytes) = rom.get(source_offset..end) else {
+                    bail!("Source copy past the end of the ROM");
+                };
+                out.extend_from_slice(bytes);
+                source_offset = end;
+            }
+            // Target copy, which can overlap what it writes
+            _ => {
+                target_offset = relative(target_offset, reader.varint()?)?;
+                for _ in 0..len {
+                    let Some(&byte) = out.get(target_offset) else {
+                        bail!("Target copy past the end of the output");
+                    };
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size {
+        bail!("Patch produced {} bytes, expected {target_size}", out.len());
+    }
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+/// Applies a BPS offset, whose lowest bit is the sign
+fn relative(offset: usize, data: usize) -> crate::Result<usize> {
+    let delta = data >> 1;
+    let offset = if data & 1 == 0 {
+        offset.checked_add(delta)
+    } else {
+        offset.checked_sub(delta)
+    };
+    offset.context("Copy offset out of range")
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> crate::Result<Vec<u8>> {
+    let target_crc = check_footer(rom, patch)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = Reader::new(&patch[..end], UPS_MAGIC.len());
+
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != rom.len() {
+        bail!(
+            "Patch is for a {source_size} byte ROM, this one is {} bytes",
+            rom.len()
+        );
+    }
+
+    check_target_size(target_size)?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    let mut offset = 0usize;
+    while reader.pos < end {
+        offset = offset
+            .checked_add(reader.varint()?)
+            .context("Patch offset overflows")?;
+        // XOR with the source until a zero byte
+        loop {
+            let byte = reader.byte()?;
+            if let Some(out) = out.get_mut(offset) {
+                *out ^= byte;
+            }
+            offset = offset.checked_add(1).context("Patch offset overflows")?;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return out;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32fast::hash(source).to_le_bytes());
+        patch.extend(crc32fast::hash(target).to_le_bytes());
+        patch.extend(crc32fast::hash(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x12_3456] {
+            let bytes = varint(value);
+            assert_eq!(Reader::new(&bytes, 0).varint().unwrap(), value);
+        }
+
+        assert!(Reader::new(&[0x7F; 10], 0).varint().is_err());
+        assert!(Reader::new(&[0x00; 16], 0).varint().is_err());
+    }
+
+    #[test]
+    fn test_hostile_sizes() {
+        let rom = [0u8; 8];
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(varint(rom.len()));
+        patch.extend(varint(MAX_ROM_SIZE + 1));
+        let patch = with_footer(patch, &rom, &[]);
+        assert!(apply(&rom, &patch).is_err());
+
+        // A target copy far longer than the target
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(varint(rom.len()));
+        patch.extend(varint(4));
+        patch.extend(varint(0));
+        // Target read of a single byte
+        patch.extend(varint(1));
+        patch.push(0xAA);
+        patch.extend(varint((usize::MAX >> 3) << 2 | 3));
+        patch.extend(varint(0));
+        let patch = with_footer(patch, &rom, &[]);
+        assert!(apply(&rom, &patch).is_err());
+    }
+
+    #[test]
+    fn test_ips() {
+        let rom = [0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        // Copy record
+        patch.extend([0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // Run length record past the end of the ROM
+        patch.extend([0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend(IPS_EOF);
+        assert_eq!(
+            apply(&rom, &patch).unwrap(),
+            [0, 0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC]
+        );
+
+        patch.extend([0x00, 0x00, 0x04]);
+        assert_eq!(apply(&rom, &patch).unwrap(), [0, 0, 0xAA, 0xBB]);
+
+        assert!(apply(&rom, &patch[..10]).is_err());
+    }
+
+    #[test]
+    fn test_ups() {
+        let rom = b"HELLO WORLD".to_vec();
+        let target = b"HELLO THERE!".to_vec();
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(varint(rom.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(6));
+        patch.extend(rom[6..].iter().zip(&target[6..]).map(|(a, b)| a ^ b));
+        patch.push(target[11]);
+        patch.push(0);
+        let patch = with_footer(patch, &rom, &target);
+        assert_eq!(apply(&rom, &patch).unwrap(), target);
+
+        let error = apply(b"HELLO WORLD?", &patch).unwrap_err();
+        assert!(format!("{error:#}").contains("Patch is for a ROM with CRC32"));
+    }
+
+    #[test]
+    fn test_bps() {
+        let rom = b"ABCDEFGH".to_vec();
+        let target = b"ABCDxyxyxyEF".to_vec();
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(varint(rom.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(0));
+        // Source read "ABCD"
+        patch.extend(varint((4 - 1) << 2));
+        // Target read "xy"
+        patch.extend(varint((2 - 1) << 2 | 1));
+        patch.extend(b"xy");
+        // Target copy "xyxy" from offset 4, overlapping itself
+        patch.extend(varint((4 - 1) << 2 | 3));
+        patch.extend(varint(4 << 1));
+        // Source copy "EF" from offset 4
+        patch.extend(varint((2 - 1) << 2 | 2));
+        patch.extend(varint(4 << 1));
+        let good = with_footer(patch.clone(), &rom, &target);
+        assert_eq!(apply(&rom, &good).unwrap(), target);
+
+        let bad = with_footer(patch, &rom, b"something else");
+        let error = apply(&rom, &bad).unwrap_err();
+        assert!(format!("{error:#}").contains("Patched ROM has CRC32"));
+    }
+}
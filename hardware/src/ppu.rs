@@ -0,0 +1,1038 @@
+//! The picture processing unit: LCD timing, its registers, VRAM and OAM
+//!
+//! <https://gbdev.io/pandocs/Rendering.html>
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::debug;
+
+use crate::palette::Palette;
+use crate::Interrupts;
+
+mod fifo;
+mod scanline;
+mod sprites;
+
+use fifo::Fifo;
+pub use sprites::{Sprite, SPRITES_PER_LINE};
+
+pub const VRAM_SIZE: usize = 0x2000;
+pub const OAM_SIZE: usize = 0xA0;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const DOTS_PER_LINE: u16 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+/// The first line of VBlank
+pub const VISIBLE_LINES: u8 = 144;
+const OAM_SCAN_DOTS: u16 = 80;
+/// Mode 3 without any of the penalties for scrolling, the window and objects
+const MIN_DRAWING_DOTS: u16 = 172;
+
+/// How the PPU draws each line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PpuBackend {
+    /// A whole line at a time at the end of mode 3, which always lasts 172 dots
+    #[default]
+    Scanline,
+    /// A dot at a time through the background and object FIFOs, so mode 3
+    /// lasts as long as it does on hardware and registers can change mid-line
+    Fifo,
+}
+
+impl PpuBackend {
+    pub const NAMES: [(Self, &'static str); 2] =
+        [(Self::Scanline, "scanline"), (Self::Fifo, "fifo")];
+}
+
+impl FromStr for PpuBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|&(backend, _)| backend)
+            .ok_or_else(|| format!("unknown PPU backend {s:?}, expected scanline or fifo"))
+    }
+}
+
+impl fmt::Display for PpuBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (_, name) = Self::NAMES
+            .iter()
+            .find(|(backend, _)| backend == self)
+            .unwrap();
+        f.write_str(name)
+    }
+}
+
+/// STAT bits 0-1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// 0xFF40-0xFF4B, except DMA
+///
+/// <https://gbdev.io/pandocs/LCDC.html>
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LcdRegisters {
+    pub lcdc: u8,
+    /// Only the interrupt selects in bits 3-6, the rest is computed on read
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+}
+
+impl LcdRegisters {
+    pub const fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub const fn window_map(&self) -> u16 {
+        if self.lcdc & 0x40 == 0 {
+            0x9800
+        } else {
+            0x9C00
+        }
+    }
+
+    pub const fn window_enabled(&self) -> bool {
+        self.lcdc & 0x20 != 0
+    }
+
+    /// Whether tiles are addressed unsigned from 0x8000, or signed from 0x9000
+    pub const fn unsigned_tiles(&self) -> bool {
+        self.lcdc & 0x10 != 0
+    }
+
+    pub const fn bg_map(&self) -> u16 {
+        if self.lcdc & 0x08 == 0 {
+            0x9800
+        } else {
+            0x9C00
+        }
+    }
+
+    /// Whether objects are 8x16 rather than 8x8
+    pub const fn tall_objects(&self) -> bool {
+        self.lcdc & 0x04 != 0
+    }
+
+    pub const fn objects_enabled(&self) -> bool {
+        self.lcdc & 0x02 != 0
+    }
+
+    /// On DMG this blanks both the background and the window
+    pub const fn bg_enabled(&self) -> bool {
+        self.lcdc & 0x01 != 0
+    }
+}
+
+/// Maps a color index through BGP, OBP0 or OBP1 to a shade
+pub const fn apply_palette(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Ppu {
+    pub regs: LcdRegisters,
+    backend: PpuBackend,
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    mode: Mode,
+    /// Dot within the current line, 0-455
+    dot: u16,
+    /// The internal STAT interrupt line, which only interrupts when it rises
+    stat_line: bool,
+    /// The LCD was just turned on and line 0 skips OAM scan
+    first_line: bool,
+    /// The LCD was just turned on and this frame is never shown
+    skip_frame: bool,
+    /// A frame finished since the last [`Ppu::take_frame_ready`]
+    frame_ready: bool,
+    /// WY matched LY at some point this frame
+    window_triggered: bool,
+    /// The window's own line counter, which only advances on lines it's drawn on
+    window_line: u8,
+    /// Shades of the frame being drawn, tagged with the layer they're from
+    back: Vec<u8>,
+    /// The last finished frame, still tagged
+    shown: Vec<u8>,
+    /// Shades of the last finished frame
+    frame: Vec<u8>,
+    palette: Palette,
+    /// [`Ppu::frame`] through [`Ppu::palette`] as RGBA
+    rgba: Vec<u8>,
+    /// The objects OAM scan selected for this line
+    line_sprites: Vec<Sprite>,
+    fifo: Fifo,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new(PpuBackend::default())
+    }
+}
+
+impl Ppu {
+    pub fn new(backend: PpuBackend) -> Self {
+        Self {
+            regs: LcdRegisters::default(),
+            backend,
+            vram: vec![0; VRAM_SIZE],
+            oam: vec![0; OAM_SIZE],
+            mode: Mode::HBlank,
+            dot: 0,
+            stat_line: false,
+            first_line: false,
+            skip_frame: false,
+            frame_ready: false,
+            window_triggered: false,
+            window_line: 0,
+            back: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shown: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            palette: Palette::default(),
+            rgba: Palette::default()
+                .to_rgba(&[0])
+                .repeat(SCREEN_WIDTH * SCREEN_HEIGHT),
+            line_sprites: Vec::new(),
+            fifo: Fifo::default(),
+        }
+    }
+
+    pub const fn backend(&self) -> PpuBackend {
+        self.backend
+    }
+
+    pub const fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub const fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    /// The last finished frame, one shade (0-3) per pixel in rows of [`SCREEN_WIDTH`]
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// [`Ppu::frame`] with four bytes of RGBA per pixel
+    pub fn frame_rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub const fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Changes the colors of [`Ppu::frame_rgba`], including the frame already shown
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.rgba = self.palette.to_rgba(&self.shown);
+    }
+
+    /// Whether a frame was finished since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    /// The CPU can't reach VRAM while it's being drawn from
+    pub fn vram_accessible(&self) -> bool {
+        !self.regs.lcd_enabled() || self.mode != Mode::Drawing
+    }
+
+    /// The CPU can't reach OAM during OAM scan or while it's being drawn from
+    pub const fn oam_accessible(&self) -> bool {
+        !self.regs.lcd_enabled() || matches!(self.mode, Mode::HBlank | Mode::VBlank)
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[usize::from(address - 0x8000)]
+    }
+
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        self.vram[usize::from(address - 0x8000)] = value;
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[usize::from(address - 0xFE00)]
+    }
+
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        self.oam[usize::from(address - 0xFE00)] = value;
+    }
+
+    pub const fn read_register(&self, address: u16) -> u8 {
+        let regs = &self.regs;
+        match address {
+            0xFF40 => regs.lcdc,
+            0xFF41 => {
+                let coincidence = (regs.ly == regs.lyc) as u8;
+                0x80 | regs.stat | coincidence << 2 | self.mode as u8
+            }
+            0xFF42 => regs.scy,
+            0xFF43 => regs.scx,
+            0xFF44 => regs.ly,
+            0xFF45 => regs.lyc,
+            0xFF47 => regs.bgp,
+            0xFF48 => regs.obp0,
+            0xFF49 => regs.obp1,
+            0xFF4A => regs.wy,
+            0xFF4B => regs.wx,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes a register, returning the interrupts that requests
+    pub fn write_register(&mut self, address: u16, value: u8) -> u8 {
+        let regs = &mut self.regs;
+        match address {
+            0xFF40 => {
+                let was_enabled = regs.lcd_enabled();
+                regs.lcdc = value;
+                match (was_enabled, regs.lcd_enabled()) {
+                    (true, false) => self.lcd_off(),
+                    (false, true) => self.lcd_on(),
+                    _ => {}
+                }
+            }
+            0xFF41 => regs.stat = value & 0x78,
+            0xFF42 => regs.scy = value,
+            0xFF43 => regs.scx = value,
+            0xFF45 => regs.lyc = value,
+            0xFF47 => regs.bgp = value,
+            0xFF48 => regs.obp0 = value,
+            0xFF49 => regs.obp1 = value,
+            0xFF4A => regs.wy = value,
+            0xFF4B => regs.wx = value,
+            // LY is read only
+            _ => {}
+        }
+
+        // Changing the selects or LYC can raise the line straight away
+        if self.update_stat_line() {
+            Interrupts::STAT
+        } else {
+            0
+        }
+    }
+
+    fn lcd_off(&mut self) {
+        debug!("LCD off");
+        if self.mode != Mode::VBlank {
+            debug!("LCD turned off outside of VBlank, which can damage real hardware");
+        }
+        self.regs.ly = 0;
+        self.dot = 0;
+        self.mode = Mode::HBlank;
+        self.stat_line = false;
+        // The screen goes blank
+        self.shown.fill(0);
+        self.frame.fill(0);
+        self.rgba = self.palette.to_rgba(&self.shown);
+        self.frame_ready = true;
+    }
+
+    fn lcd_on(&mut self) {
+        debug!("LCD on");
+        self.regs.ly = 0;
+        self.dot = 0;
+        // Line 0 starts in mode 0 instead of OAM scan
+        self.mode = Mode::HBlank;
+        self.first_line = true;
+        self.skip_frame = true;
+        self.window_triggered = false;
+        self.window_line = 0;
+    }
+
+    /// Advances `dots` dots (T-cycles), returning the interrupts requested
+    pub fn tick(&mut self, dots: u32) -> u8 {
+        if !self.regs.lcd_enabled() {
+            return 0;
+        }
+
+        let mut interrupts = 0;
+        for _ in 0..dots {
+            interrupts |= self.step();
+        }
+        interrupts
+    }
+
+    fn step(&mut self) -> u8 {
+        let mut interrupts = 0;
+        self.dot += 1;
+
+        match self.mode {
+            Mode::OamScan if self.dot == OAM_SCAN_DOTS => self.start_drawing(),
+            Mode::HBlank if self.first_line && self.dot == OAM_SCAN_DOTS => {
+                self.first_line = false;
+                self.start_drawing();
+            }
+            Mode::Drawing => {
+                let done = match self.backend {
+                    PpuBackend::Scanline => {
+                        self.dot == OAM_SCAN_DOTS + MIN_DRAWING_DOTS && {
+                            self.render_line();
+                            true
+                        }
+                    }
+                    PpuBackend::Fifo => self.step_fifo(),
+                };
+                if done {
+                    self.mode = Mode::HBlank;
+                }
+            }
+            _ => {}
+        }
+
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.regs.ly += 1;
+
+            if self.regs.ly == VISIBLE_LINES {
+                self.mode = Mode::VBlank;
+                interrupts |= Interrupts::VBLANK;
+                self.end_frame();
+            } else if self.regs.ly == LINES_PER_FRAME {
+                self.regs.ly = 0;
+                self.mode = Mode::OamScan;
+                self.window_triggered = false;
+                self.window_line = 0;
+            } else if self.regs.ly < VISIBLE_LINES {
+                self.mode = Mode::OamScan;
+            }
+        }
+
+        if self.update_stat_line() {
+            interrupts |= Interrupts::STAT;
+        }
+        interrupts
+    }
+
+    /// Moves from OAM scan to mode 3
+    fn start_drawing(&mut self) {
+        self.mode = Mode::Drawing;
+        if self.regs.ly == self.regs.wy {
+            self.window_triggered = true;
+        }
+        self.line_sprites = sprites::scan_oam(&self.oam, self.regs);
+        if self.backend == PpuBackend::Fifo {
+            self.fifo = Fifo::new(self.regs, &self.line_sprites);
+        }
+    }
+
+    fn end_frame(&mut self) {
+        if self.skip_frame {
+            self.skip_frame = false;
+            return;
+        }
+        std::mem::swap(&mut self.shown, &mut self.back);
+        for (shade, &pixel) in self.frame.iter_mut().zip(&self.shown) {
+            *shade = pixel & 0x03;
+        }
+        self.rgba = self.palette.to_rgba(&self.shown);
+        self.frame_ready = true;
+    }
+
+    /// Recomputes the STAT interrupt line, returning whether it rose.
+    ///
+    /// All the sources are combined into one line, so one that's already high
+    /// blocks the others from interrupting.
+    const fn update_stat_line(&mut self) -> bool {
+        let stat = self.regs.stat;
+        let line = self.regs.lcd_enabled()
+            && ((stat & 0x40 != 0 && self.regs.ly == self.regs.lyc)
+                || match self.mode {
+                    Mode::HBlank => stat & 0x08 != 0,
+                    // The OAM source also fires at the start of line 144
+                    Mode::VBlank => {
+                        stat & 0x10 != 0
+                            || (stat & 0x20 != 0 && self.regs.ly == VISIBLE_LINES && self.dot == 0)
+                    }
+                    Mode::OamScan => stat & 0x20 != 0,
+                    Mode::Drawing => false,
+                });
+
+        let rose = line && !self.stat_line;
+        self.stat_line = line;
+        rose
+    }
+}
+
+/// Where `tile`'s data starts in VRAM, for the background and window
+fn tile_address(regs: LcdRegisters, tile: u8) -> usize {
+    if regs.unsigned_tiles() {
+        usize::from(tile) * 16
+    } else {
+        0x1000_usize.wrapping_add_signed(isize::from(tile.cast_signed()) * 16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOTS_PER_FRAME: u32 = DOTS_PER_LINE as u32 * LINES_PER_FRAME as u32;
+    /// Both should draw the same frames
+    const BACKENDS: [PpuBackend; 2] = [PpuBackend::Scanline, PpuBackend::Fifo];
+
+    fn enabled() -> Ppu {
+        enabled_with(PpuBackend::Scanline)
+    }
+
+    fn enabled_with(backend: PpuBackend) -> Ppu {
+        let mut ppu = Ppu::new(backend);
+        ppu.write_register(0xFF40, 0x91);
+        // Get past the first line after turning the LCD on
+        ppu.tick(u32::from(DOTS_PER_LINE));
+        ppu
+    }
+
+    /// Runs until the start of `ly`
+    fn tick_to_line(ppu: &mut Ppu, ly: u8) {
+        ppu.tick(1);
+        while ppu.regs.ly != ly || ppu.dot() != 0 {
+            ppu.tick(1);
+        }
+    }
+
+    /// Runs until the next frame is finished
+    fn render(ppu: &mut Ppu) -> Vec<u8> {
+        while !ppu.take_frame_ready() {
+            ppu.tick(1);
+        }
+        ppu.frame().to_vec()
+    }
+
+    /// Writes a tile whose rows are all `low` and `high`
+    fn write_tile(ppu: &mut Ppu, address: u16, low: u8, high: u8) {
+        for row in 0..8 {
+            ppu.write_vram(address + row * 2, low);
+            ppu.write_vram(address + row * 2 + 1, high);
+        }
+    }
+
+    fn pixel(frame: &[u8], x: usize, y: usize) -> u8 {
+        frame[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn test_line_timing() {
+        let mut ppu = enabled();
+        assert_eq!((ppu.regs.ly, ppu.mode()), (1, Mode::OamScan));
+
+        ppu.tick(79);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ppu.tick(1);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ppu.tick(u32::from(MIN_DRAWING_DOTS));
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        assert_eq!(ppu.read_register(0xFF41) & 0x03, 0);
+
+        ppu.tick(u32::from(DOTS_PER_LINE - OAM_SCAN_DOTS - MIN_DRAWING_DOTS));
+        assert_eq!((ppu.regs.ly, ppu.mode()), (2, Mode::OamScan));
+    }
+
+    #[test]
+    fn test_vblank() {
+        let mut ppu = enabled();
+        let to_vblank = u32::from(DOTS_PER_LINE) * 143;
+        assert_eq!(ppu.tick(to_vblank - 1) & Interrupts::VBLANK, 0);
+        assert_eq!(ppu.tick(1), Interrupts::VBLANK);
+        assert_eq!((ppu.regs.ly, ppu.mode()), (144, Mode::VBlank));
+        // The first frame after turning the LCD on is never shown
+        assert!(!ppu.take_frame_ready());
+
+        ppu.tick(u32::from(DOTS_PER_LINE) * 10);
+        assert_eq!((ppu.regs.ly, ppu.mode()), (0, Mode::OamScan));
+        assert_eq!(ppu.tick(DOTS_PER_FRAME), Interrupts::VBLANK);
+        assert!(ppu.take_frame_ready());
+    }
+
+    #[test]
+    fn test_lyc_interrupt() {
+        let mut ppu = enabled();
+        ppu.write_register(0xFF45, 5);
+        ppu.write_register(0xFF41, 0x40);
+
+        assert_eq!(ppu.tick(u32::from(DOTS_PER_LINE) * 4 - 1), 0);
+        assert_eq!(ppu.tick(1), Interrupts::STAT);
+        assert_eq!(ppu.read_register(0xFF41), 0x80 | 0x40 | 0x04 | 0x02);
+
+        // Setting LYC to the current line interrupts immediately
+        ppu.write_register(0xFF45, 0);
+        assert_eq!(ppu.write_register(0xFF45, 5), Interrupts::STAT);
+    }
+
+    #[test]
+    fn test_stat_blocking() {
+        let mut ppu = enabled();
+        // HBlank and LYC on line 1, which is the current line
+        ppu.write_register(0xFF45, 1);
+        assert_eq!(ppu.write_register(0xFF41, 0x48), Interrupts::STAT);
+
+        // The line is still high from LYC when HBlank starts
+        assert_eq!(ppu.tick(u32::from(OAM_SCAN_DOTS + MIN_DRAWING_DOTS)), 0);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+
+        // On line 2 HBlank interrupts on its own
+        ppu.tick(u32::from(DOTS_PER_LINE - OAM_SCAN_DOTS - MIN_DRAWING_DOTS));
+        assert_eq!(
+            ppu.tick(u32::from(OAM_SCAN_DOTS + MIN_DRAWING_DOTS)),
+            Interrupts::STAT
+        );
+    }
+
+    #[test]
+    fn test_access_by_mode() {
+        let mut ppu = Ppu::default();
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+
+        ppu = enabled();
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        assert!(ppu.vram_accessible() && !ppu.oam_accessible());
+        ppu.tick(u32::from(OAM_SCAN_DOTS));
+        assert!(!ppu.vram_accessible() && !ppu.oam_accessible());
+        ppu.tick(u32::from(MIN_DRAWING_DOTS));
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+        tick_to_line(&mut ppu, VISIBLE_LINES);
+        assert!(ppu.vram_accessible() && ppu.oam_accessible());
+    }
+
+    #[test]
+    fn test_lcd_off_and_on() {
+        let mut ppu = enabled();
+        ppu.tick(1000);
+        ppu.write_register(0xFF40, 0x11);
+        assert_eq!(ppu.regs.ly, 0);
+        assert_eq!(ppu.read_register(0xFF41) & 0x03, 0);
+        assert_eq!(ppu.tick(DOTS_PER_FRAME), 0);
+        assert_eq!(ppu.regs.ly, 0);
+
+        // Line 0 after turning it back on starts in mode 0, without OAM scan
+        ppu.write_register(0xFF40, 0x91);
+        ppu.tick(4);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        ppu.tick(u32::from(OAM_SCAN_DOTS) - 4);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+    }
+
+    #[test]
+    fn test_background() {
+        for backend in BACKENDS {
+            let mut ppu = Ppu::new(backend);
+            // Colors 0, 1, 2, 3, 0, 1, 2, 3
+            write_tile(&mut ppu, 0x8010, 0b0101_0101, 0b0011_0011);
+            ppu.write_vram(0x9800, 1);
+            ppu.write_register(0xFF47, 0b1110_0100);
+            ppu.write_register(0xFF40, 0x91);
+            render(&mut ppu);
+
+            let frame = render(&mut ppu);
+            assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+            assert_eq!(&frame[..9], &[0, 1, 2, 3, 0, 1, 2, 3, 0]);
+            assert_eq!(pixel(&frame, 3, 7), 3);
+            assert_eq!(pixel(&frame, 3, 8), 0);
+            assert_eq!(&ppu.frame_rgba()[12..16], &[0x00, 0x00, 0x00, 0xFF]);
+
+            // Scrolling wraps around the 256x256 map
+            ppu.write_register(0xFF43, 254);
+            ppu.write_register(0xFF42, 4);
+            let frame = render(&mut ppu);
+            assert_eq!(&frame[..4], &[0, 0, 0, 1]);
+            assert_eq!(pixel(&frame, 5, 3), 3);
+            assert_eq!(pixel(&frame, 5, 4), 0);
+
+            // BGP maps the colors to shades
+            ppu.write_register(0xFF47, 0b0001_1011);
+            let frame = render(&mut ppu);
+            assert_eq!(&frame[..4], &[3, 3, 3, 2]);
+        }
+    }
+
+    #[test]
+    fn test_tile_addressing() {
+        for backend in BACKENDS {
+            let mut ppu = Ppu::new(backend);
+            ppu.write_register(0xFF47, 0b1110_0100);
+            write_tile(&mut ppu, 0x8000, 0xFF, 0x00);
+            write_tile(&mut ppu, 0x9000, 0x00, 0xFF);
+            write_tile(&mut ppu, 0x8800, 0xFF, 0xFF);
+            ppu.write_vram(0x9801, 0x80);
+
+            // Unsigned from 0x8000
+            ppu.write_register(0xFF40, 0x91);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+            assert_eq!((pixel(&frame, 0, 0), pixel(&frame, 8, 0)), (1, 3));
+
+            // Signed from 0x9000
+            ppu.write_register(0xFF40, 0x81);
+            let frame = render(&mut ppu);
+            assert_eq!((pixel(&frame, 0, 0), pixel(&frame, 8, 0)), (2, 3));
+
+            // The second map
+            ppu.write_vram(0x9C00, 0x80);
+            ppu.write_register(0xFF40, 0x89);
+            let frame = render(&mut ppu);
+            assert_eq!((pixel(&frame, 0, 0), pixel(&frame, 8, 0)), (3, 2));
+
+            // The background disabled, which is white even if BGP says otherwise
+            ppu.write_register(0xFF47, 0xFF);
+            ppu.write_register(0xFF40, 0x80);
+            assert!(render(&mut ppu).iter().all(|&shade| shade == 0));
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        for backend in BACKENDS {
+            let mut ppu = Ppu::new(backend);
+            ppu.write_register(0xFF47, 0b1110_0100);
+            write_tile(&mut ppu, 0x8010, 0xFF, 0xFF);
+            for i in 0..32 * 32 {
+                ppu.write_vram(0x9C00 + i, 1);
+            }
+            ppu.write_register(0xFF4A, 72);
+            ppu.write_register(0xFF4B, 80 + 7);
+            ppu.write_register(0xFF40, 0xF1);
+            render(&mut ppu);
+
+            let frame = render(&mut ppu);
+            assert_eq!(pixel(&frame, 80, 71), 0);
+            assert_eq!(pixel(&frame, 79, 72), 0);
+            assert_eq!(pixel(&frame, 80, 72), 3);
+            assert_eq!(pixel(&frame, 159, 143), 3);
+        }
+    }
+
+    #[test]
+    fn test_window_line_counter() {
+        for backend in BACKENDS {
+            let mut ppu = Ppu::new(backend);
+            ppu.write_register(0xFF47, 0b1110_0100);
+            write_tile(&mut ppu, 0x8010, 0xFF, 0xFF);
+            // Only the window's first row of tiles is filled
+            for i in 0..32 {
+                ppu.write_vram(0x9C00 + i, 1);
+            }
+            ppu.write_register(0xFF4B, 7);
+            ppu.write_register(0xFF40, 0xF1);
+            render(&mut ppu);
+
+            // The window is drawn on lines 0-3, then hidden until line 100
+            tick_to_line(&mut ppu, 4);
+            ppu.write_register(0xFF40, 0xD1);
+            tick_to_line(&mut ppu, 100);
+            ppu.write_register(0xFF40, 0xF1);
+            let frame = render(&mut ppu);
+
+            assert_eq!(pixel(&frame, 0, 3), 3);
+            assert_eq!(pixel(&frame, 0, 4), 0);
+            // It carries on from its 5th line rather than LY - WY
+            for y in 100..104 {
+                assert_eq!(pixel(&frame, 0, y), 3);
+            }
+            assert_eq!(pixel(&frame, 0, 104), 0);
+        }
+    }
+
+    #[test]
+    fn test_lcd_off_blanks_the_frame() {
+        for backend in BACKENDS {
+            let mut ppu = Ppu::new(backend);
+            ppu.write_register(0xFF47, 0b1110_0100);
+            write_tile(&mut ppu, 0x8000, 0xFF, 0xFF);
+            ppu.write_register(0xFF40, 0x91);
+            render(&mut ppu);
+            assert!(render(&mut ppu).iter().all(|&shade| shade == 3));
+
+            ppu.write_register(0xFF40, 0x11);
+            assert!(ppu.take_frame_ready());
+            assert!(ppu.frame().iter().all(|&shade| shade == 0));
+            assert!(ppu.frame_rgba().iter().all(|&byte| byte == 0xFF));
+        }
+    }
+
+    fn write_sprite(ppu: &mut Ppu, index: u16, sprite: [u8; 4]) {
+        for (i, byte) in sprite.into_iter().enumerate() {
+            ppu.write_oam(0xFE00 + index * 4 + i as u16, byte);
+        }
+    }
+
+    /// Objects enabled over a blank background, with tile 1 in color 1,
+    /// tile 2 in color 2 and tile 3 with only its left column in color 3
+    fn with_sprites(backend: PpuBackend) -> Ppu {
+        let mut ppu = Ppu::new(backend);
+        write_tile(&mut ppu, 0x8010, 0xFF, 0x00);
+        write_tile(&mut ppu, 0x8020, 0x00, 0xFF);
+        write_tile(&mut ppu, 0x8030, 0x80, 0x80);
+        ppu.write_register(0xFF47, 0b1110_0100);
+        ppu.write_register(0xFF48, 0b1110_0100);
+        ppu.write_register(0xFF49, 0b0001_1011);
+        ppu.write_register(0xFF40, 0x93);
+        ppu
+    }
+
+    #[test]
+    fn test_sprites() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            // At (10, 20) with OBP1
+            write_sprite(&mut ppu, 0, [36, 18, 1, 0x10]);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+
+            assert_eq!(pixel(&frame, 9, 20), 0);
+            assert_eq!(pixel(&frame, 10, 20), 2);
+            assert_eq!(pixel(&frame, 17, 27), 2);
+            assert_eq!(pixel(&frame, 18, 27), 0);
+            assert_eq!(pixel(&frame, 17, 28), 0);
+
+            // Disabled
+            ppu.write_register(0xFF40, 0x91);
+            assert!(render(&mut ppu).iter().all(|&shade| shade == 0));
+        }
+    }
+
+    #[test]
+    fn test_sprite_with_fine_scroll() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            ppu.write_register(0xFF43, 3);
+            // At the left edge of the screen
+            write_sprite(&mut ppu, 0, [16, 8, 1, 0x00]);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+            assert_eq!(&frame[..9], &[1, 1, 1, 1, 1, 1, 1, 1, 0], "{backend}");
+        }
+    }
+
+    #[test]
+    fn test_sprite_flip_and_size() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            write_tile(&mut ppu, 0x8000, 0x00, 0x00);
+            ppu.write_vram(0x800E, 0x01);
+            // Tile 3, X flipped
+            write_sprite(&mut ppu, 0, [16, 8, 3, 0x20]);
+            // 8x8 tile 0 Y flipped, so its last row at the top
+            write_sprite(&mut ppu, 1, [16, 16, 0, 0x40]);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+            assert_eq!(
+                &frame[..16],
+                &[0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1]
+            );
+            assert_eq!(pixel(&frame, 15, 1), 0);
+            assert_eq!(pixel(&frame, 7, 8), 0);
+
+            // 8x16 uses tiles 0 and 1 for an odd index, so flipped tile 1 ends up on top
+            write_sprite(&mut ppu, 0, [16, 8, 1, 0x40]);
+            write_sprite(&mut ppu, 1, [0, 0, 0, 0]);
+            ppu.write_register(0xFF40, 0x97);
+            let frame = render(&mut ppu);
+            assert_eq!(pixel(&frame, 0, 0), 1);
+            assert_eq!(pixel(&frame, 0, 7), 1);
+            assert_eq!((pixel(&frame, 7, 8), pixel(&frame, 6, 8)), (1, 0));
+            assert_eq!(pixel(&frame, 0, 15), 0);
+        }
+    }
+
+    #[test]
+    fn test_palette_layers() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            write_sprite(&mut ppu, 0, [16, 8, 1, 0x00]);
+            write_sprite(&mut ppu, 1, [16, 16, 1, 0x10]);
+            render(&mut ppu);
+            render(&mut ppu);
+
+            // Each layer is colored separately, including in the frame already shown
+            let red = [[0xFF, 0, 0]; 4];
+            let blue = [[0, 0, 0xFF]; 4];
+            ppu.set_palette(Palette {
+                obj0: red,
+                obj1: blue,
+                ..Palette::GRAYSCALE
+            });
+            let rgba = ppu.frame_rgba();
+            assert_eq!(&rgba[..4], &[0xFF, 0, 0, 0xFF]);
+            assert_eq!(&rgba[8 * 4..][..4], &[0, 0, 0xFF, 0xFF]);
+            assert_eq!(&rgba[16 * 4..][..4], &[0xFF; 4]);
+        }
+    }
+
+    #[test]
+    fn test_overlapping_sprites() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            // A later entry further left is drawn over the earlier one
+            write_sprite(&mut ppu, 0, [16, 12, 2, 0]);
+            write_sprite(&mut ppu, 1, [16, 8, 1, 0]);
+            // At the same X the first entry wins
+            write_sprite(&mut ppu, 2, [32, 8, 2, 0]);
+            write_sprite(&mut ppu, 3, [32, 8, 1, 0]);
+            // Transparent pixels of the winner show the object behind it
+            write_sprite(&mut ppu, 4, [48, 8, 3, 0]);
+            write_sprite(&mut ppu, 5, [48, 8, 2, 0]);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+
+            assert_eq!(&frame[..12], &[1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2]);
+            assert_eq!(&frame[16 * SCREEN_WIDTH..][..8], &[2; 8]);
+            assert_eq!(&frame[32 * SCREEN_WIDTH..][..3], &[3, 2, 2]);
+        }
+    }
+
+    #[test]
+    fn test_sprite_bg_priority() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            // The background's left tile is color 1, the right one color 0
+            write_tile(&mut ppu, 0x8040, 0xFF, 0x00);
+            ppu.write_vram(0x9800, 4);
+            write_sprite(&mut ppu, 0, [16, 8, 2, 0x80]);
+            write_sprite(&mut ppu, 1, [16, 16, 2, 0x80]);
+            write_sprite(&mut ppu, 2, [32, 8, 2, 0]);
+            ppu.write_vram(0x9840, 4);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+
+            // Behind color 1, but over color 0
+            assert_eq!(pixel(&frame, 0, 0), 1);
+            assert_eq!(pixel(&frame, 8, 0), 2);
+            // Without the flag it's in front
+            assert_eq!(pixel(&frame, 0, 16), 2);
+
+            // Color 0 is what counts, not the shade BGP maps it to
+            ppu.write_register(0xFF47, 0b1110_0111);
+            let frame = render(&mut ppu);
+            assert_eq!((pixel(&frame, 0, 0), pixel(&frame, 8, 0)), (1, 2));
+        }
+    }
+
+    #[test]
+    fn test_sprites_per_line() {
+        for backend in BACKENDS {
+            let mut ppu = with_sprites(backend);
+            // 12 objects on line 0, spread across the screen
+            for i in 0..12 {
+                write_sprite(&mut ppu, i, [16, 8 + i as u8 * 12, 1, 0]);
+            }
+            // Offscreen objects still count towards the limit on line 8
+            for i in 12..22 {
+                write_sprite(&mut ppu, i, [24, 0, 1, 0]);
+            }
+            write_sprite(&mut ppu, 22, [24, 8, 1, 0]);
+            render(&mut ppu);
+            let frame = render(&mut ppu);
+
+            for i in 0..10 {
+                assert_eq!(pixel(&frame, i * 12, 0), 1, "object {i}");
+            }
+            assert_eq!(pixel(&frame, 120, 0), 0);
+            assert_eq!(pixel(&frame, 132, 0), 0);
+            assert_eq!(pixel(&frame, 0, 8), 0);
+            assert_eq!(pixel(&frame, 0, 16), 0);
+        }
+    }
+
+    /// How many dots mode 3 lasts on the next line
+    fn drawing_dots(ppu: &mut Ppu) -> u16 {
+        tick_to_line(ppu, ppu.regs.ly + 1);
+        while ppu.mode() != Mode::Drawing {
+            ppu.tick(1);
+        }
+        let start = ppu.dot();
+        while ppu.mode() == Mode::Drawing {
+            ppu.tick(1);
+        }
+        ppu.dot() - start
+    }
+
+    #[test]
+    fn test_fifo_mode_3_length() {
+        let mut ppu = enabled_with(PpuBackend::Fifo);
+        assert_eq!(drawing_dots(&mut ppu), MIN_DRAWING_DOTS);
+
+        // Fine scrolling throws away pixels at the start of the line
+        ppu.write_register(0xFF43, 0x13);
+        assert_eq!(drawing_dots(&mut ppu), MIN_DRAWING_DOTS + 3);
+        ppu.write_register(0xFF43, 0);
+
+        // The window restarts the fetcher
+        ppu.write_register(0xFF4B, 40 + 7);
+        ppu.write_register(0xFF40, 0xB1);
+        assert_eq!(drawing_dots(&mut ppu), MIN_DRAWING_DOTS + 6);
+        ppu.write_register(0xFF40, 0x93);
+
+        // Objects pause it for 6-11 dots each
+        write_sprite(&mut ppu, 0, [0, 8, 0, 0]);
+        write_sprite(&mut ppu, 1, [0, 60, 0, 0]);
+        for i in [0, 1] {
+            ppu.write_oam(0xFE00 + i * 4, ppu.regs.ly + 1 + 16);
+        }
+        let dots = drawing_dots(&mut ppu);
+        assert!(
+            (MIN_DRAWING_DOTS + 12..=MIN_DRAWING_DOTS + 22).contains(&dots),
+            "{dots}"
+        );
+
+        // The scanline renderer always takes the minimum
+        let mut ppu = enabled();
+        write_sprite(&mut ppu, 0, [16 + 2, 8, 0, 0]);
+        ppu.write_register(0xFF43, 0x13);
+        ppu.write_register(0xFF40, 0x93);
+        assert_eq!(drawing_dots(&mut ppu), MIN_DRAWING_DOTS);
+    }
+
+    #[test]
+    fn test_fifo_mid_line_palette() {
+        let mut ppu = Ppu::new(PpuBackend::Fifo);
+        write_tile(&mut ppu, 0x8000, 0xFF, 0xFF);
+        ppu.write_register(0xFF47, 0b1110_0100);
+        ppu.write_register(0xFF40, 0x91);
+        render(&mut ppu);
+
+        tick_to_line(&mut ppu, 10);
+        while ppu.mode() != Mode::Drawing {
+            ppu.tick(1);
+        }
+        ppu.tick(90);
+        ppu.write_register(0xFF47, 0);
+        let frame = render(&mut ppu);
+
+        let row = &frame[10 * SCREEN_WIDTH..11 * SCREEN_WIDTH];
+        let changed = row.iter().position(|&shade| shade == 0).unwrap();
+        assert!((60..100).contains(&changed), "{changed}");
+        assert!(row[..changed].iter().all(|&shade| shade == 3));
+        assert!(row[changed..].iter().all(|&shade| shade == 0));
+        assert!(frame[..10 * SCREEN_WIDTH].iter().all(|&shade| shade == 3));
+    }
+}
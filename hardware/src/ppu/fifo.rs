@@ -0,0 +1,234 @@
+//! Draws a dot at a time through the background and object FIFOs
+//!
+//! <https://gbdev.io/pandocs/pixel_fifo.html>
+use std::collections::VecDeque;
+
+use super::sprites::{sprite_pixel, Sprite};
+use super::{apply_palette, tile_address, LcdRegisters, Ppu, SCREEN_WIDTH};
+use crate::palette::Layer;
+
+/// Dots of the background fetch at the start of each line, which is thrown away
+const WARMUP_DOTS: u8 = 6;
+/// Dots spent fetching an object, once the background fetcher is done
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+/// The background fetcher's steps, where all but pushing take two dots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Step {
+    #[default]
+    Tile,
+    Low,
+    High,
+    /// Waits until the background FIFO is empty
+    Push,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjectPixel {
+    color: u8,
+    sprite: Sprite,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fifo {
+    /// Color indices
+    bg: VecDeque<u8>,
+    objects: VecDeque<ObjectPixel>,
+    step: Step,
+    /// Dots spent on the current step
+    step_dots: u8,
+    /// Tiles fetched this line, or since the window started
+    fetch_x: u8,
+    tile: u8,
+    /// Row within the tile
+    row: u8,
+    low: u8,
+    high: u8,
+    /// Pixels sent to the LCD this line
+    lx: u8,
+    /// Pixels to throw away rather than send to the LCD
+    discard: u8,
+    warmup: u8,
+    window: bool,
+    /// Objects on this line yet to be fetched, rightmost first
+    pending: Vec<Sprite>,
+    /// The object being fetched and the dots left until it's done
+    fetching: Option<(Sprite, u8)>,
+}
+
+impl Fifo {
+    pub fn new(regs: LcdRegisters, sprites: &[Sprite]) -> Self {
+        let mut pending = sprites.to_vec();
+        // On DMG the leftmost object wins, then the first in OAM
+        pending.sort_by_key(|sprite| std::cmp::Reverse((sprite.x, sprite.index)));
+        Self {
+            // The background scrolls by whole tiles in the fetcher and the rest here
+            discard: regs.scx % 8,
+            warmup: WARMUP_DOTS,
+            pending,
+            ..Self::default()
+        }
+    }
+
+    /// Advances the background fetcher a dot
+    fn fetch(&mut self, vram: &[u8], regs: LcdRegisters, window_line: u8) {
+        if self.step == Step::Push {
+            if self.bg.is_empty() {
+                self.bg.extend(
+                    (0..8)
+                        .rev()
+                        .map(|bit| ((self.high >> bit) & 1) << 1 | ((self.low >> bit) & 1)),
+                );
+                self.fetch_x = self.fetch_x.wrapping_add(1);
+                self.step = Step::Tile;
+            }
+            return;
+        }
+
+        self.step_dots += 1;
+        if self.step_dots < 2 {
+            return;
+        }
+        self.step_dots = 0;
+
+        match self.step {
+            Step::Tile => {
+                let (map, x, y) = if self.window {
+                    (regs.window_map(), self.fetch_x, window_line)
+                } else {
+                    let x = (regs.scx / 8).wrapping_add(self.fetch_x) % 32;
+                    (regs.bg_map(), x, regs.scy.wrapping_add(regs.ly))
+                };
+                let index =
+                    usize::from(map - 0x8000) + usize::from(y / 8) * 32 + usize::from(x % 32);
+                self.tile = vram[index];
+                self.row = y % 8;
+                self.step = Step::Low;
+            }
+            Step::Low => {
+                self.low = vram[tile_address(regs, self.tile) + usize::from(self.row) * 2];
+                self.step = Step::High;
+            }
+            Step::High => {
+                self.high = vram[tile_address(regs, self.tile) + usize::from(self.row) * 2 + 1];
+                self.step = Step::Push;
+            }
+            Step::Push => unreachable!(),
+        }
+    }
+
+    /// Mixes `sprite` into the object FIFO, under the pixels already there
+    fn merge(&mut self, vram: &[u8], regs: LcdRegisters, sprite: Sprite) {
+        for column in 0..8 {
+            // Columns left of the screen, or already shifted out
+            let Some(offset) = (sprite.x + column).checked_sub(self.lx + 8) else {
+                continue;
+            };
+            let pixel = ObjectPixel {
+                color: sprite_pixel(vram, regs, sprite, column),
+                sprite,
+            };
+
+            match self.objects.get_mut(usize::from(offset)) {
+                Some(existing) if existing.color == 0 => *existing = pixel,
+                Some(_) => {}
+                None => self.objects.push_back(pixel),
+            }
+        }
+    }
+}
+
+impl Ppu {
+    /// Advances mode 3 by a dot, returning whether the line is finished
+    pub(super) fn step_fifo(&mut self) -> bool {
+        let regs = self.regs;
+        let fifo = &mut self.fifo;
+
+        if fifo.warmup > 0 {
+            fifo.warmup -= 1;
+            return false;
+        }
+
+        // Restart the fetcher on the window, when WX is reached
+        if !fifo.window
+            && self.window_triggered
+            && regs.bg_enabled()
+            && regs.window_enabled()
+            && (fifo.lx + 7 == regs.wx || (fifo.lx == 0 && regs.wx < 7))
+        {
+            fifo.window = true;
+            fifo.bg.clear();
+            fifo.step = Step::Tile;
+            fifo.step_dots = 0;
+            fifo.fetch_x = 0;
+            // WX 0-6 cuts off the window's left edge
+            if fifo.lx == 0 {
+                fifo.discard = 7 - regs.wx.min(7);
+            }
+        }
+
+        if let Some((sprite, dots)) = fifo.fetching {
+            if dots > 1 {
+                fifo.fetching = Some((sprite, dots - 1));
+            } else {
+                fifo.fetching = None;
+                fifo.merge(&self.vram, regs, sprite);
+            }
+            return false;
+        }
+
+        // Objects stall the LCD once it reaches them
+        if let Some(&sprite) = fifo.pending.last() {
+            if sprite.x <= fifo.lx + 8 {
+                if !regs.objects_enabled() {
+                    fifo.pending.pop();
+                } else if fifo.step == Step::Push {
+                    fifo.pending.pop();
+                    fifo.fetching = Some((sprite, OBJECT_FETCH_DOTS - 1));
+                    return false;
+                } else {
+                    // Wait for the background fetch to finish first
+                    fifo.fetch(&self.vram, regs, self.window_line);
+                    return false;
+                }
+            }
+        }
+
+        fifo.fetch(&self.vram, regs, self.window_line);
+
+        let Some(color) = fifo.bg.pop_front() else {
+            return false;
+        };
+        // Only background pixels are thrown away for fine scrolling
+        if fifo.discard > 0 {
+            fifo.discard -= 1;
+            return false;
+        }
+        let object = fifo.objects.pop_front();
+
+        let color = if regs.bg_enabled() { color } else { 0 };
+        // Palettes are read as each pixel goes out, so they can change mid-line
+        let shade = match object {
+            Some(object)
+                if object.color != 0
+                    && regs.objects_enabled()
+                    && !(object.sprite.behind_bg() && color != 0) =>
+            {
+                let shade = apply_palette(object.sprite.palette(&regs), object.color);
+                object.sprite.layer().tag(shade)
+            }
+            // Disabling the background blanks it to white, whatever BGP says
+            _ if !regs.bg_enabled() => Layer::Background.tag(0),
+            _ => Layer::Background.tag(apply_palette(regs.bgp, color)),
+        };
+        let y = usize::from(regs.ly);
+        self.back[y * SCREEN_WIDTH + usize::from(fifo.lx)] = shade;
+        fifo.lx += 1;
+
+        let done = usize::from(fifo.lx) == SCREEN_WIDTH;
+        if done && fifo.window {
+            self.window_line += 1;
+        }
+        done
+    }
+}
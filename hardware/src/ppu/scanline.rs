@@ -0,0 +1,107 @@
+//! Draws a whole line at once, at the end of mode 3
+use super::sprites::{sprite_pixel, Sprite};
+use super::{apply_palette, tile_address, LcdRegisters, Ppu, SCREEN_WIDTH};
+use crate::palette::Layer;
+
+impl Ppu {
+    /// Draws the background, window and objects for the current line
+    pub(super) fn render_line(&mut self) {
+        let regs = self.regs;
+        let y = usize::from(regs.ly);
+
+        // WX is the window's left edge plus 7
+        let window_start =
+            (self.window_triggered && regs.bg_enabled() && regs.window_enabled() && regs.wx <= 166)
+                .then(|| usize::from(regs.wx).saturating_sub(7));
+
+        // Color indices rather than shades, since objects are drawn behind colors 1-3
+        let mut colors = [0; SCREEN_WIDTH];
+        if regs.bg_enabled() {
+            for (x, color) in colors.iter_mut().enumerate() {
+                *color = match window_start {
+                    Some(start) if x >= start => {
+                        let window_x = (x - start) as u8;
+                        tile_pixel(
+                            &self.vram,
+                            regs,
+                            regs.window_map(),
+                            window_x,
+                            self.window_line,
+                        )
+                    }
+                    _ => {
+                        let bg_x = regs.scx.wrapping_add(x as u8);
+                        let bg_y = regs.scy.wrapping_add(regs.ly);
+                        tile_pixel(&self.vram, regs, regs.bg_map(), bg_x, bg_y)
+                    }
+                };
+            }
+        }
+
+        let row = &mut self.back[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+        for (pixel, &color) in row.iter_mut().zip(&colors) {
+            // Disabling the background blanks it to white, whatever BGP says
+            let shade = if regs.bg_enabled() {
+                apply_palette(regs.bgp, color)
+            } else {
+                0
+            };
+            *pixel = Layer::Background.tag(shade);
+        }
+
+        if regs.objects_enabled() {
+            draw_sprites(&self.vram, regs, &self.line_sprites, &colors, row);
+        }
+
+        if window_start.is_some() {
+            self.window_line += 1;
+        }
+    }
+}
+
+/// The color index at (`x`, `y`) of the 256x256 background drawn from `map`
+fn tile_pixel(vram: &[u8], regs: LcdRegisters, map: u16, x: u8, y: u8) -> u8 {
+    let map_index = usize::from(map - 0x8000) + usize::from(y / 8) * 32 + usize::from(x / 8);
+    let line = tile_address(regs, vram[map_index]) + usize::from(y % 8) * 2;
+    let bit = 7 - x % 8;
+    let low = (vram[line] >> bit) & 1;
+    let high = (vram[line + 1] >> bit) & 1;
+    high << 1 | low
+}
+
+/// Draws `sprites` over `row`, where `colors` are the background's color indices
+fn draw_sprites(
+    vram: &[u8],
+    regs: LcdRegisters,
+    sprites: &[Sprite],
+    colors: &[u8; SCREEN_WIDTH],
+    row: &mut [u8],
+) {
+    // On DMG the leftmost object wins, then the first in OAM
+    let mut sprites = sprites.to_vec();
+    sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
+
+    for (x, pixel) in row.iter_mut().enumerate() {
+        let screen_x = x as u8 + 8;
+        let Some((sprite, color)) = sprites
+            .iter()
+            .filter(|sprite| screen_x >= sprite.x && screen_x < sprite.x.saturating_add(8))
+            .map(|sprite| {
+                (
+                    sprite,
+                    sprite_pixel(vram, regs, *sprite, screen_x - sprite.x),
+                )
+            })
+            .find(|&(_, color)| color != 0)
+        else {
+            continue;
+        };
+
+        if sprite.behind_bg() && colors[x] != 0 {
+            continue;
+        }
+        *pixel = sprite
+            .layer()
+            .tag(apply_palette(sprite.palette(&regs), color));
+    }
+}
@@ -0,0 +1,97 @@
+//! Objects, and how OAM scan picks them
+use super::LcdRegisters;
+use crate::palette::Layer;
+
+/// An entry in OAM
+///
+/// <https://gbdev.io/pandocs/OAM.html>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+    /// Position in OAM, which breaks ties between objects at the same X
+    pub index: u8,
+    /// The top edge plus 16
+    pub y: u8,
+    /// The left edge plus 8
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[u8], index: u8) -> Self {
+        let entry = &oam[usize::from(index) * 4..];
+        Self {
+            index,
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            flags: entry[3],
+        }
+    }
+
+    /// Background and window colors 1-3 are drawn over this object
+    pub const fn behind_bg(&self) -> bool {
+        self.flags & 0x80 != 0
+    }
+
+    pub const fn y_flip(&self) -> bool {
+        self.flags & 0x40 != 0
+    }
+
+    pub const fn x_flip(&self) -> bool {
+        self.flags & 0x20 != 0
+    }
+
+    /// Which of OBP0 and OBP1 it's drawn with
+    pub const fn layer(&self) -> Layer {
+        if self.flags & 0x10 == 0 {
+            Layer::Obj0
+        } else {
+            Layer::Obj1
+        }
+    }
+
+    pub const fn palette(&self, regs: &LcdRegisters) -> u8 {
+        if self.flags & 0x10 == 0 {
+            regs.obp0
+        } else {
+            regs.obp1
+        }
+    }
+}
+
+/// How many objects the OAM scan selects per line
+pub const SPRITES_PER_LINE: usize = 10;
+
+/// Selects the first [`SPRITES_PER_LINE`] objects in OAM that overlap LY,
+/// regardless of whether they're on screen horizontally
+pub(super) fn scan_oam(oam: &[u8], regs: LcdRegisters) -> Vec<Sprite> {
+    let height = if regs.tall_objects() { 16 } else { 8 };
+    let line = regs.ly + 16;
+    (0..40)
+        .map(|index| Sprite::from_oam(oam, index))
+        .filter(|sprite| line >= sprite.y && line < sprite.y.saturating_add(height))
+        .take(SPRITES_PER_LINE)
+        .collect()
+}
+
+/// The color index of column `x` of `sprite` on the current line
+pub(super) fn sprite_pixel(vram: &[u8], regs: LcdRegisters, sprite: Sprite, x: u8) -> u8 {
+    let height = if regs.tall_objects() { 16 } else { 8 };
+    let mut y = regs.ly + 16 - sprite.y;
+    if sprite.y_flip() {
+        y = height - 1 - y;
+    }
+    // 8x16 objects ignore the lowest bit of the tile index
+    let tile = if regs.tall_objects() {
+        sprite.tile & 0xFE
+    } else {
+        sprite.tile
+    };
+
+    let line = usize::from(tile) * 16 + usize::from(y) * 2;
+    let bit = if sprite.x_flip() { x } else { 7 - x };
+    let low = (vram[line] >> bit) & 1;
+    let high = (vram[line + 1] >> bit) & 1;
+    high << 1 | low
+}
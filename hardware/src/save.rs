@@ -0,0 +1,315 @@
+//! Battery backed save files, laid out like other emulators': cartridge RAM
+//! followed by whatever the mapper appends, such as the 48 byte clock footer
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, warn};
+
+use crate::cart::Cartridge;
+
+/// How long a write may sit in memory before it's flushed to disk
+pub const FLUSH_DELAY: Duration = Duration::from_secs(3);
+
+#[derive(Debug)]
+pub struct SaveFile {
+    path: PathBuf,
+    cart: Arc<RwLock<Cartridge>>,
+    /// When the oldest write that isn't on disk yet happened
+    dirty_since: Option<Instant>,
+    /// Set when an existing save couldn't be read, so it must not be overwritten
+    load_failed: bool,
+}
+
+impl SaveFile {
+    pub fn new(path: impl Into<PathBuf>, cart: Arc<RwLock<Cartridge>>) -> Self {
+        Self {
+            path: path.into(),
+            cart,
+            dirty_since: None,
+            load_failed: false,
+        }
+    }
+
+    /// `game.gb` and `game.zip` save to `game.sav`, and so does `game.gb.gz`
+    pub fn for_rom(rom: impl AsRef<Path>, cart: Arc<RwLock<Cartridge>>) -> Self {
+        Self::new(rom_save_path(rom.as_ref()), cart)
+    }
+
+    /// Names the save after the game's canonical name, next to the ROM.
+    ///
+    /// A save named after the ROM is still used until one with the canonical name exists.
+    pub fn for_game(rom: impl AsRef<Path>, name: &str, cart: Arc<RwLock<Cartridge>>) -> Self {
+        let rom = rom.as_ref();
+        let dir = rom.parent().unwrap_or_else(|| Path::new(""));
+        // Canonical names can contain characters some filesystems reject
+        let name: String = name
+            .chars()
+            .map(|c| if r#"/\:*?"<>|"#.contains(c) { '_' } else { c })
+            .collect();
+        let path = dir.join(format!("{name}.sav"));
+
+        let by_rom = rom_save_path(rom);
+        if !path.exists() && by_rom.exists() {
+            info!("Using {} rather than {}", by_rom.display(), path.display());
+            return Self::new(by_rom, cart);
+        }
+        Self::new(path, cart)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the save file into the cartridge, if both exist.
+    ///
+    /// If the file exists but can't be read, it's never written to afterwards.
+    pub fn load(&mut self) -> crate::Result<()> {
+        if !self
+            .cart
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .header
+            .cartridge_type
+            .has_battery()
+        {
+            return Ok(());
+        }
+
+        if !self.path.exists() {
+            debug!("No save file at {}", self.path.display());
+            return Ok(());
+        }
+
+        let data = fs::read(&self.path).inspect_err(|_| self.load_failed = true)?;
+        info!(
+            "Loading {} bytes of save data from {}",
+            data.len(),
+            self.path.display()
+        );
+        let mut cart = self.cart.write().unwrap_or_else(PoisonError::into_inner);
+        cart.load_save_data(&data);
+        cart.take_dirty();
+        drop(cart);
+        Ok(())
+    }
+
+    /// Flushes the save once a write is [`FLUSH_DELAY`] old
+    pub fn poll(&mut self) -> crate::Result<()> {
+        self.poll_at(Instant::now())
+    }
+
+    pub fn poll_at(&mut self, now: Instant) -> crate::Result<()> {
+        if self
+            .cart
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take_dirty()
+        {
+            self.dirty_since.get_or_insert(now);
+        }
+
+        match self.dirty_since {
+            Some(since) if now.duration_since(since) >= FLUSH_DELAY => self.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn flush(&mut self) -> crate::Result<()> {
+        self.dirty_since = None;
+        if self.load_failed {
+            warn!(
+                "Not saving over {}, which failed to load",
+                self.path.display()
+            );
+            return Ok(());
+        }
+
+        let Some(data) = self
+            .cart
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .save_data()
+        else {
+            return Ok(());
+        };
+
+        // Write next to the save and rename over it, so a crash can't leave half a file
+        let temp = self.path.with_extension("sav.tmp");
+        fs::write(&temp, data)?;
+        fs::rename(&temp, &self.path)?;
+        debug!("Saved to {}", self.path.display());
+        Ok(())
+    }
+}
+
+fn rom_save_path(rom: &Path) -> PathBuf {
+    let mut rom = rom.to_path_buf();
+    if rom
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+    {
+        rom.set_extension("");
+    }
+    rom.with_extension("sav")
+}
+
+impl Drop for SaveFile {
+    /// Flushes unsaved writes, and the time a cartridge clock was last seen at
+    fn drop(&mut self) {
+        let mut cart = self.cart.write().unwrap_or_else(PoisonError::into_inner);
+        let changed = cart.take_dirty() || cart.header.cartridge_type.has_timer();
+        drop(cart);
+        if !changed && self.dirty_since.is_none() {
+            return;
+        }
+
+        if let Err(e) = self.flush() {
+            error!("Failed to write {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cart::rtc::{ManualClock, RTC_FOOTER_SIZE};
+    use crate::testing::{make_rom, temp_path};
+
+    /// Latches the clock and reads the minutes and seconds
+    fn latched_time(cart: &mut Cartridge) -> (u8, u8) {
+        cart.write(0x0000, 0x0A);
+        cart.write(0x6000, 0x00);
+        cart.write(0x6000, 0x01);
+        cart.write(0x4000, 0x08);
+        let seconds = cart.read(0xA000);
+        cart.write(0x4000, 0x09);
+        (cart.read(0xA000), seconds)
+    }
+
+    #[test]
+    fn test_save_path() {
+        let cart = Arc::new(RwLock::new(Cartridge::default()));
+        for rom in ["roms/game.gb", "roms/game.zip", "roms/game.gb.gz"] {
+            let save = SaveFile::for_rom(rom, Arc::clone(&cart));
+            assert_eq!(save.path(), Path::new("roms/game.sav"));
+        }
+
+        let save = SaveFile::for_game(
+            "roms/tetris.zip",
+            "Tetris (World) (Rev 1)",
+            Arc::clone(&cart),
+        );
+        assert_eq!(save.path(), Path::new("roms/Tetris (World) (Rev 1).sav"));
+
+        // An existing save named after the ROM wins over a missing canonical one
+        let old = temp_path("old.sav");
+        fs::write(&old, [0]).unwrap();
+        let save = SaveFile::for_game(old.with_extension("gb"), "Tetris (World)", cart);
+        assert_eq!(save.path(), old);
+        drop(save);
+        fs::remove_file(&old).unwrap();
+    }
+
+    #[test]
+    fn test_delayed_flush() {
+        let path = temp_path("flush.sav");
+        let cart = Arc::new(RwLock::new(
+            Cartridge::new(make_rom("SAVE", 0x03, 0, 2)).unwrap(),
+        ));
+        let mut save = SaveFile::new(&path, Arc::clone(&cart));
+
+        cart.write().unwrap().write(0x0000, 0x0A);
+        cart.write().unwrap().write(0xA000, 0x42);
+        let start = Instant::now();
+        save.poll_at(start).unwrap();
+        assert!(!path.exists());
+
+        save.poll_at(start + FLUSH_DELAY).unwrap();
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[0], 0x42);
+
+        // Reload into a fresh cartridge
+        let cart = Arc::new(RwLock::new(
+            Cartridge::new(make_rom("SAVE", 0x03, 0, 2)).unwrap(),
+        ));
+        let mut save = SaveFile::new(&path, Arc::clone(&cart));
+        save.load().unwrap();
+        assert_eq!(cart.read().unwrap().ram()[0], 0x42);
+        drop(save);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flush_after_panic() {
+        let path = temp_path("poisoned.sav");
+        let cart = Arc::new(RwLock::new(
+            Cartridge::new(make_rom("SAVE", 0x03, 0, 2)).unwrap(),
+        ));
+        let save = SaveFile::new(&path, Arc::clone(&cart));
+        cart.write().unwrap().write(0x0000, 0x0A);
+        cart.write().unwrap().write(0xA000, 0x42);
+
+        // The CPU thread panicking while it holds the cartridge mustn't lose the save
+        let thread_cart = Arc::clone(&cart);
+        let _ = std::thread::spawn(move || {
+            let _cart = thread_cart.write().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(cart.is_poisoned());
+
+        drop(save);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rtc_footer() {
+        let path = temp_path("rtc.sav");
+        let clock = ManualClock::new(1000);
+        let mut cart = Cartridge::new(make_rom("CLOCK", 0x10, 0, 2)).unwrap();
+        cart.set_clock(clock.clone());
+        clock.advance(90);
+        let cart = Arc::new(RwLock::new(cart));
+        SaveFile::new(&path, Arc::clone(&cart)).flush().unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            (0x2000 + RTC_FOOTER_SIZE) as u64
+        );
+
+        // The clock keeps running while the emulator is off
+        clock.advance(60);
+        let mut cart = Cartridge::new(make_rom("CLOCK", 0x10, 0, 2)).unwrap();
+        cart.set_clock(clock);
+        let cart = Arc::new(RwLock::new(cart));
+        let mut save = SaveFile::new(&path, Arc::clone(&cart));
+        save.load().unwrap();
+        assert_eq!(latched_time(&mut cart.write().unwrap()), (2, 30));
+        drop(save);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flush_only_when_safe() {
+        let path = temp_path("untouched.sav");
+        let cart = Arc::new(RwLock::new(
+            Cartridge::new(make_rom("SAVE", 0x03, 0, 2)).unwrap(),
+        ));
+        drop(SaveFile::new(&path, Arc::clone(&cart)));
+        assert!(!path.exists());
+
+        // A save that can't be read is left alone, even after the game writes
+        fs::create_dir(&path).unwrap();
+        let mut save = SaveFile::new(&path, Arc::clone(&cart));
+        assert!(save.load().is_err());
+        cart.write().unwrap().write(0x0000, 0x0A);
+        cart.write().unwrap().write(0xA000, 0x42);
+        save.flush().unwrap();
+        drop(save);
+        assert!(!path.with_extension("sav.tmp").exists());
+        fs::remove_dir(&path).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+//! PNG screenshots of the framebuffer, without needing a window
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use tracing::info;
+
+use crate::ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Writes the PPU's last finished frame to `path`, `scale` times the LCD's size
+pub fn save_png(ppu: &Ppu, path: &Path, scale: u32) -> crate::Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    encode_png(BufWriter::new(file), ppu.frame_rgba(), scale)?;
+    info!("Saved screenshot to {}", path.display());
+    Ok(())
+}
+
+/// Encodes a frame of RGBA pixels as a PNG, scaling it up by a whole number
+pub fn encode_png(writer: impl Write, rgba: &[u8], scale: u32) -> crate::Result<()> {
+    ensure!(scale > 0, "Screenshot scale must be at least 1");
+    ensure!(
+        rgba.len() == SCREEN_WIDTH * SCREEN_HEIGHT * 4,
+        "Expected a {SCREEN_WIDTH}x{SCREEN_HEIGHT} RGBA frame, got {} bytes",
+        rgba.len()
+    );
+
+    let width = SCREEN_WIDTH as u32 * scale;
+    let height = SCREEN_HEIGHT as u32 * scale;
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&scale_rgba(rgba, SCREEN_WIDTH, scale as usize))?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Nearest neighbour scaling, so each pixel becomes a `scale` by `scale` square
+fn scale_rgba(rgba: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    rgba.chunks(width * 4)
+        .flat_map(|row| {
+            let scaled: Vec<u8> = row
+                .chunks(4)
+                .flat_map(|pixel| pixel.repeat(scale))
+                .collect();
+            scaled.repeat(scale)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).unwrap();
+        buffer.truncate(info.buffer_size());
+        (info.width, info.height, buffer)
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let mut rgba = vec![0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+        // A black pixel at (1, 0)
+        rgba[4..7].fill(0);
+
+        let mut png = Vec::new();
+        encode_png(&mut png, &rgba, 1).unwrap();
+        assert_eq!(decode(&png), (160, 144, rgba.clone()));
+
+        let mut png = Vec::new();
+        encode_png(&mut png, &rgba, 3).unwrap();
+        let (width, height, pixels) = decode(&png);
+        assert_eq!((width, height), (480, 432));
+        let pixel = |x: usize, y: usize| &pixels[(y * 480 + x) * 4..][..4];
+        assert_eq!(pixel(2, 2), [0xFF; 4]);
+        assert_eq!(pixel(3, 0), [0, 0, 0, 0xFF]);
+        assert_eq!(pixel(5, 2), [0, 0, 0, 0xFF]);
+        assert_eq!(pixel(5, 3), [0xFF; 4]);
+        assert_eq!(pixel(6, 0), [0xFF; 4]);
+
+        assert!(encode_png(Vec::new(), &rgba, 0).is_err());
+        assert!(encode_png(Vec::new(), &rgba[4..], 1).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Fixtures shared by the unit tests
+use std::path::PathBuf;
+
+use crate::header::{compute_global_checksum, compute_header_checksum, CartridgeHeader};
+use crate::NINTENDO_HEADER;
+
+/// A ROM image with a valid logo and checksums, and whatever header fields a test needs
+#[derive(Debug, Clone, Copy)]
+pub struct TestRom<'a> {
+    pub title: &'a str,
+    pub cartridge_type: u8,
+    /// The header's size code, the image is `0x8000 << rom_size` bytes
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub cgb_flag: u8,
+    /// 0x33 means the new licensee code is used instead
+    pub old_licensee: u8,
+    pub new_licensee: [u8; 2],
+}
+
+impl Default for TestRom<'_> {
+    fn default() -> Self {
+        Self {
+            title: "TEST",
+            cartridge_type: 0x00,
+            rom_size: 0,
+            ram_size: 0,
+            cgb_flag: 0x00,
+            old_licensee: 0x00,
+            new_licensee: [0; 2],
+        }
+    }
+}
+
+impl TestRom<'_> {
+    pub fn build(self) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000 << self.rom_size];
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_HEADER);
+        rom[0x0134..0x0134 + self.title.len()].copy_from_slice(self.title.as_bytes());
+        if self.cgb_flag != 0 {
+            rom[0x0143] = self.cgb_flag;
+        }
+        rom[0x0144..0x0146].copy_from_slice(&self.new_licensee);
+        rom[0x0147] = self.cartridge_type;
+        rom[0x0148] = self.rom_size;
+        rom[0x0149] = self.ram_size;
+        rom[0x014B] = self.old_licensee;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    pub fn header(self) -> CartridgeHeader {
+        CartridgeHeader::parse(&self.build()).unwrap()
+    }
+}
+
+/// Shorthand for the common case of a [`TestRom`] that only sets the cartridge fields
+pub fn make_rom(title: &str, cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    TestRom {
+        title,
+        cartridge_type,
+        rom_size,
+        ram_size,
+        ..TestRom::default()
+    }
+    .build()
+}
+
+/// Recomputes both checksums once the header is filled in
+fn fix_checksums(rom: &mut [u8]) {
+    rom[0x014D] = compute_header_checksum(rom);
+    let [hi, lo] = compute_global_checksum(rom).to_be_bytes();
+    rom[0x014E] = hi;
+    rom[0x014F] = lo;
+}
+
+/// A path in the temp directory unique to this test run, with nothing there yet
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("gbem-test-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
@@ -0,0 +1,164 @@
+//! DIV, TIMA, TMA and TAC
+//!
+//! <https://gbdev.io/pandocs/Timer_and_Divider_Registers.html>
+//! <https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html>
+
+#[derive(Debug, Default, Clone)]
+pub struct Timer {
+    /// Incremented every T-cycle, DIV is the upper byte
+    counter: u16,
+    tima: u8,
+    modulo: u8,
+    control: u8,
+    /// TIMA overflowed last cycle, it reloads and interrupts on the next
+    reload_pending: bool,
+}
+
+impl Timer {
+    /// The bit of the counter whose falling edge increments TIMA
+    const fn tima_bit(&self) -> u16 {
+        match self.control & 0x03 {
+            0 => 1 << 9, // 4096 Hz
+            1 => 1 << 3, // 262144 Hz
+            2 => 1 << 5, // 65536 Hz
+            _ => 1 << 7, // 16384 Hz
+        }
+    }
+
+    /// Whether the timer is enabled and the selected counter bit is set, TIMA
+    /// increments when this goes from high to low
+    const fn signal(&self) -> bool {
+        self.control & 0x04 != 0 && self.counter & self.tima_bit() != 0
+    }
+
+    const fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        // Reads as 0 for the cycle before the reload
+        self.tima = tima;
+        self.reload_pending = overflow;
+    }
+
+    /// Advances one M-cycle, returning whether the timer interrupt was requested
+    pub const fn tick(&mut self) -> bool {
+        let interrupt = self.reload_pending;
+        if self.reload_pending {
+            self.reload_pending = false;
+            self.tima = self.modulo;
+        }
+
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+
+        interrupt
+    }
+
+    pub const fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.modulo,
+            0xFF07 => self.control | 0xF8,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let before = self.signal();
+        match address {
+            // Resetting the counter can drop the selected bit
+            0xFF04 => self.counter = 0,
+            0xFF05 => {
+                // Writing during the delay cancels the reload and the interrupt
+                self.reload_pending = false;
+                self.tima = value;
+            }
+            0xFF06 => self.modulo = value,
+            // So can disabling the timer or switching to a lower bit
+            0xFF07 => self.control = value & 0x07,
+            _ => unreachable!(),
+        }
+
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_div() {
+        let mut timer = Timer::default();
+        for _ in 0..64 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF04), 1);
+
+        timer.write(0xFF04, 0x12);
+        assert_eq!(timer.read(0xFF04), 0);
+    }
+
+    #[test]
+    fn test_overflow_delay() {
+        let mut timer = Timer::default();
+        // 262144 Hz, an increment every 4 M-cycles
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+
+        for _ in 0..4 {
+            assert!(!timer.tick());
+        }
+        assert_eq!(timer.read(0xFF05), 0x00);
+
+        assert!(timer.tick());
+        assert_eq!(timer.read(0xFF05), 0xAB);
+    }
+
+    #[test]
+    fn test_write_cancels_reload() {
+        let mut timer = Timer::default();
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        for _ in 0..4 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF05), 0x00);
+
+        timer.write(0xFF05, 0x42);
+        assert!(!timer.tick());
+        assert_eq!(timer.read(0xFF05), 0x42);
+    }
+
+    #[test]
+    fn test_falling_edge_glitches() {
+        let mut timer = Timer::default();
+        timer.write(0xFF07, 0x05);
+        // Bit 3 is set after 2 M-cycles
+        timer.tick();
+        timer.tick();
+        assert_eq!(timer.read(0xFF05), 0);
+
+        // Resetting DIV drops bit 3
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+
+        // Disabling the timer while the bit is set
+        timer.tick();
+        timer.tick();
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 2);
+
+        // Switching to bit 9, which is clear, while bit 3 is set
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x04);
+        assert_eq!(timer.read(0xFF05), 3);
+        assert_eq!(timer.read(0xFF07), 0xFC);
+    }
+}